@@ -3,9 +3,10 @@ extern crate susanoo;
 #[macro_use]
 extern crate hyper;
 
-use susanoo::{Susanoo, Context, AsyncResult, Router, Middleware, Chain};
+use susanoo::{Susanoo, Context, AsyncResult, Router, Chain, Html};
 use susanoo::contrib::hyper::{Get, StatusCode, Response};
 use susanoo::contrib::hyper::header::{Authorization, Basic};
+use susanoo::contrib::futures::{future, Future};
 use susanoo::contrib::typemap::Key;
 
 header! {
@@ -39,18 +40,6 @@ impl std::ops::Deref for UserList {
     }
 }
 
-impl Key for UserList {
-    type Value = Self;
-}
-
-impl Middleware for UserList {
-    fn call(&self, mut ctx: Context) -> AsyncResult {
-        ctx.ext.insert::<UserList>(
-            UserList(self.0.clone()),
-        );
-        ctx.next()
-    }
-}
 
 
 
@@ -74,9 +63,7 @@ fn check_auth(mut ctx: Context) -> AsyncResult {
         }
     };
 
-    let found: Option<User> = ctx.ext
-        .get::<UserList>()
-        .unwrap()
+    let found: Option<User> = try_f!(ctx.state::<UserList>())
         .iter()
         .find(|&user| user.verify(username, password))
         .map(|u| u.clone());
@@ -100,19 +87,11 @@ fn check_auth(mut ctx: Context) -> AsyncResult {
 
 fn index(mut ctx: Context) -> AsyncResult {
     let user = ctx.ext.remove::<User>().unwrap();
-    ctx.finish(
-        Response::new()
-            .with_status(StatusCode::Ok)
-            .with_body(format!("<h1>Welcome, {}!</h1>", user.username)),
-    )
+    ctx.respond(Html(format!("<h1>Welcome, {}!</h1>", user.username)))
 }
 
 fn public(ctx: Context) -> AsyncResult {
-    ctx.finish(
-        Response::new()
-            .with_status(StatusCode::Ok)
-            .with_body("<h1>Public page</h1>"),
-    )
+    ctx.respond(Html("<h1>Public page</h1>"))
 }
 
 fn main() {
@@ -129,7 +108,7 @@ fn main() {
         .with_route(Get, "/", index)
         .with_route(Get, "/public", public);
 
-    let susanoo = Susanoo::new(chain!(UserList(users), router));
+    let susanoo = Susanoo::new(router).with_state(UserList(users));
 
     let server = susanoo.into_server("0.0.0.0:4000").unwrap();
     server.run().unwrap();
@@ -1,54 +1,44 @@
-#[macro_use]
 extern crate susanoo;
-extern crate r2d2;
 extern crate r2d2_sqlite;
+#[macro_use]
 extern crate rusqlite;
 
-use susanoo::{Context, Susanoo, AsyncResult, Router, Middleware, Chain};
-use susanoo::contrib::hyper::{Get, StatusCode, Response};
-use susanoo::contrib::futures::{future, Future};
-use susanoo::contrib::typemap::Key;
+use susanoo::{Susanoo, Failure, Router, ConnectionPool, Conn, Query, Transaction, Transactional, handler};
+use susanoo::contrib::hyper::{Get, Post, StatusCode};
+use susanoo::contrib::futures::Future;
+use susanoo::contrib::futures::future::BoxFuture;
+use susanoo::contrib::r2d2::Pool;
 
-use std::ops::Deref;
-use r2d2::Pool;
+use std::collections::HashMap;
+use std::time::Duration;
 use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::Connection as SqliteConnection;
+use rusqlite::{Connection as SqliteConnection, NO_PARAMS};
 
 
-// DB connection pool.
-struct DBPool(Pool<SqliteConnectionManager>);
+// DB connection checked out from the pool.
+type Db = Conn<SqliteConnectionManager>;
 
-impl Deref for DBPool {
-    type Target = Pool<SqliteConnectionManager>;
-    fn deref(&self) -> &Pool<SqliteConnectionManager> {
-        &self.0
-    }
-}
-
-impl Key for DBPool {
-    type Value = Self;
-}
+// Transactions of SQLite.
+struct Sqlite;
 
+impl Transactional for Sqlite {
+    type Manager = SqliteConnectionManager;
 
-struct DBMiddleware(Pool<SqliteConnectionManager>);
+    fn begin(&self, conn: &mut SqliteConnection) -> Result<(), Failure> {
+        // Take the write lock first, so that concurrent transactions do not deadlock on upgrading.
+        Ok(conn.execute_batch("BEGIN IMMEDIATE")?)
+    }
 
-impl DBMiddleware {
-    fn new(path: &str) -> Self {
-        let manager = SqliteConnectionManager::new(path);
-        let pool = r2d2::Pool::new(Default::default(), manager).unwrap();
-        DBMiddleware(pool)
+    fn commit(&self, conn: &mut SqliteConnection) -> Result<(), Failure> {
+        Ok(conn.execute_batch("COMMIT")?)
     }
-}
 
-impl Middleware for DBMiddleware {
-    fn call(&self, mut ctx: Context) -> AsyncResult {
-        ctx.ext.insert::<DBPool>(DBPool(self.0.clone()));
-        ctx.next()
+    fn rollback(&self, conn: &mut SqliteConnection) -> Result<(), Failure> {
+        Ok(conn.execute_batch("ROLLBACK")?)
     }
 }
 
 
-
 // Model
 #[derive(Debug)]
 struct Person {
@@ -66,18 +56,18 @@ impl Person {
         }
     }
 
-    fn from_row(row: &rusqlite::Row) -> Self {
-        Person {
-            id: row.get(0),
-            name: row.get(1),
-            data: row.get(2),
-        }
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Person {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            data: row.get(2)?,
+        })
     }
 
-    fn insert(&self, conn: &SqliteConnection) -> rusqlite::Result<i32> {
+    fn insert(&self, conn: &SqliteConnection) -> rusqlite::Result<usize> {
         conn.execute(
             "INSERT INTO persons (name, data) VALUES (?1, ?2)",
-            &[&self.name, &self.data],
+            params![self.name, self.data],
         )
     }
 
@@ -88,30 +78,38 @@ impl Person {
               , name  TEXT      NOT NULL
               , data  BLOB
             )"#,
-            &[],
+            NO_PARAMS,
         ).map(|_| ())
     }
 
     fn select(conn: &SqliteConnection) -> rusqlite::Result<Vec<Person>> {
         let mut stmt = conn.prepare("SELECT id,name,data FROM persons")?;
-        let people = stmt.query_map(&[], Person::from_row)?
+        let people = stmt.query_map(NO_PARAMS, Person::from_row)?
             .collect::<Result<_, _>>()?;
         Ok(people)
     }
 }
 
 
-fn index(mut ctx: Context) -> AsyncResult {
-    let people = {
-        let db = ctx.ext.get::<DBPool>().unwrap();
-        let conn = try_f!(db.get());
-        try_f!(Person::select(&*conn))
-    };
-    ctx.finish(
-        Response::new()
-            .with_status(StatusCode::Ok)
-            .with_body(format!("people: {:?}", people)),
-    )
+fn index(db: Db) -> BoxFuture<String, Failure> {
+    // The query blocks, so run it off the event loop.
+    db.run(|conn| Person::select(conn))
+        .map(|people| format!("people: {:?}", people))
+        .boxed()
+}
+
+// Inserts the people in the query (e.g. `?alice=&carol=`), all or nothing:
+// the transaction is rolled back unless all of them are valid.
+fn create(db: Db, Query(names): Query<HashMap<String, String>>) -> BoxFuture<(StatusCode, String), Failure> {
+    db.run(move |conn| -> Result<_, Failure> {
+        for name in names.keys() {
+            if name.is_empty() || name.len() > 32 {
+                return Ok((StatusCode::BadRequest, format!("invalid name: {:?}", name)));
+            }
+            Person::new(0, name).insert(conn)?;
+        }
+        Ok((StatusCode::Created, format!("created {} people", names.len())))
+    }).boxed()
 }
 
 
@@ -129,9 +127,18 @@ fn init_db(path: &str) {
 fn main() {
     init_db("app.sqlite");
 
-    let db = DBMiddleware::new("app.sqlite");
-    let router = Router::default().with_route(Get, "/", index);
-    let susanoo = Susanoo::new(chain!(db, router));
+    // Requests waiting longer than 5 seconds for a connection fail with `503 Service Unavailable`.
+    let pool = Pool::builder()
+        .max_size(10)
+        .connection_timeout(Duration::from_secs(5))
+        .build(SqliteConnectionManager::file("app.sqlite"))
+        .unwrap();
+
+    let router = Router::default()
+        .with_route(Get, "/", handler(index))
+        .with_route(Post, "/", Transaction::new(handler(create), Sqlite));
+    // As many threads as connections, so that a query waiting for a lock never starves its holder.
+    let susanoo = Susanoo::new(ConnectionPool::new(router, pool)).with_blocking_threads(10);
 
     let server = susanoo.into_server("0.0.0.0:4000").unwrap();
     server.run().unwrap();
@@ -1,13 +1,10 @@
 extern crate susanoo;
 
 use susanoo::{Context, Susanoo, AsyncResult, Router};
-use susanoo::contrib::hyper::{Get, StatusCode, Response};
+use susanoo::contrib::hyper::Get;
 
 fn hello(ctx: Context) -> AsyncResult {
-    let res = Response::new()
-        .with_status(StatusCode::Ok)
-        .with_body("Hello, world");
-    ctx.finish(res)
+    ctx.respond("Hello, world")
 }
 
 fn main() {
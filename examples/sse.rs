@@ -0,0 +1,47 @@
+extern crate susanoo;
+extern crate tokio_core;
+
+use std::time::Duration;
+
+use susanoo::{Context, Susanoo, AsyncResult, Router, Sse, Event, Html};
+use susanoo::contrib::futures::{stream, Stream};
+use susanoo::contrib::hyper::Get;
+use tokio_core::reactor::Interval;
+
+fn index(ctx: Context) -> AsyncResult {
+    ctx.respond(Html(
+        r#"<ul id="ticks"></ul>
+        <script>
+            new EventSource("/ticks").addEventListener("tick", function (e) {
+                var li = document.createElement("li");
+                li.textContent = e.data;
+                document.getElementById("ticks").appendChild(li);
+            });
+        </script>"#,
+    ))
+}
+
+fn ticks(ctx: Context) -> AsyncResult {
+    // Resume from the last event the client received.
+    let start = ctx.last_event_id()
+        .and_then(|id| id.parse::<u64>().ok())
+        .map_or(0, |id| id + 1);
+
+    let interval = Interval::new(Duration::from_secs(1), &ctx.handle().unwrap()).unwrap();
+    let events = interval.zip(stream::iter_ok(start..)).map(|((), n)| {
+        Event::new(format!("tick #{}", n))
+            .with_id(n.to_string())
+            .with_event("tick")
+    });
+
+    ctx.sse(Sse::new(events))
+}
+
+fn main() {
+    let router = Router::default()
+        .with_route(Get, "/", index)
+        .with_route(Get, "/ticks", ticks);
+    let susanoo = Susanoo::new(router);
+    let server = susanoo.into_server("0.0.0.0:4000").unwrap();
+    server.run().unwrap();
+}
@@ -0,0 +1,27 @@
+extern crate susanoo;
+
+use susanoo::{Context, Susanoo, AsyncResult, Router, Failure};
+use susanoo::contrib::hyper::{Get, StatusCode, Response};
+use susanoo::contrib::hyper::header::{ContentType, ContentDisposition, DispositionType};
+use susanoo::contrib::futures::stream;
+
+fn export(ctx: Context) -> AsyncResult {
+    let header = Some("id,name,score\n".to_owned());
+    let rows = (1..100_001).map(|id| format!("{},user{},{}\n", id, id, id % 100));
+
+    let res = Response::new()
+        .with_status(StatusCode::Ok)
+        .with_header(ContentType("text/csv".parse().unwrap()))
+        .with_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![],
+        });
+    ctx.stream(res, stream::iter_ok::<_, Failure>(header.into_iter().chain(rows)))
+}
+
+fn main() {
+    let router = Router::default().with_route(Get, "/export.csv", export);
+    let susanoo = Susanoo::new(router);
+    let server = susanoo.into_server("0.0.0.0:4000").unwrap();
+    server.run().unwrap();
+}
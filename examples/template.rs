@@ -1,83 +1,63 @@
 #[macro_use]
 extern crate susanoo;
 extern crate tera;
+#[macro_use]
+extern crate serde_json;
 
-use susanoo::{Susanoo, Router, Context, AsyncResult, Middleware, Chain};
-use susanoo::contrib::hyper::{Get, StatusCode, Response};
-use susanoo::contrib::hyper::header::ContentType;
-use susanoo::contrib::typemap::Key;
-use std::sync::Arc;
-use tera::{Tera, Context as TeraContext};
-
-#[derive(Clone)]
-struct TeraMiddleware(Arc<Tera>);
-
-impl TeraMiddleware {
-    fn new() -> Self {
-        let mut tera = Tera::default();
-        tera.add_raw_template(
-            "index.html",
-            r#"
-            <html>
-            <head>
-                <title>Susanoo example</title>
-            </head>
-            <body>
-                <h1>Hi, {{ name }}!</h1>
-                <p>
-                    {{ text }}
-                </p>
-            </body>
-            </html>
-            "#,
-        ).unwrap();
-        TeraMiddleware(Arc::new(tera))
-    }
-}
-
-impl Key for TeraMiddleware {
-    type Value = Self;
-}
+use susanoo::{Susanoo, Router, Context, AsyncResult, Chain, Csrf, Templates, TeraRenderer};
+use susanoo::contrib::hyper::{Get, Post};
+use susanoo::contrib::futures::{future, Future};
+use tera::Tera;
 
-impl Middleware for TeraMiddleware {
-    fn call(&self, mut ctx: Context) -> AsyncResult {
-        ctx.ext.insert::<TeraMiddleware>(self.clone());
-        ctx.next()
-    }
+fn load_templates() -> Templates {
+    // With templates in a directory, reload them on every request during development:
+    // TeraRenderer::from_dir("templates").unwrap().with_dev_mode(true)
+    let mut tera = Tera::default();
+    tera.add_raw_template(
+        "index.html",
+        r#"
+        <html>
+        <head>
+            <title>Susanoo example</title>
+        </head>
+        <body>
+            <h1>Hi, {{ current_user }}!</h1>
+            <p>
+                {{ text }}
+            </p>
+            <form method="post" action="/">
+                <input type="hidden" name="csrf_token" value="{{ csrf_token }}">
+                <button type="submit">Say hello</button>
+            </form>
+        </body>
+        </html>
+        "#,
+    ).unwrap();
+    Templates::new(TeraRenderer::new(tera))
 }
 
 
-trait TeraMiddlewareExt {
-    fn render(&mut self, name: &str, ctx: &TeraContext) -> Response;
+fn current_user(mut ctx: Context) -> AsyncResult {
+    try_f!(ctx.set_template_global("current_user", &"Alice"));
+    ctx.next()
 }
 
-impl TeraMiddlewareExt for Context {
-    fn render(&mut self, name: &str, ctx: &TeraContext) -> Response {
-        let body = {
-            let tera = self.ext.get::<TeraMiddleware>().unwrap();
-            tera.0.render(name, ctx).unwrap()
-        };
-        Response::new()
-            .with_status(StatusCode::Ok)
-            .with_header(ContentType::html())
-            .with_body(body)
-    }
+fn index(ctx: Context) -> AsyncResult {
+    let rendered = ctx.render("index.html", &json!({ "text": "Welcome to the wonderland" }));
+    ctx.respond(rendered)
 }
 
-
-fn index(mut ctx: Context) -> AsyncResult {
-    let mut tera_ctx = TeraContext::default();
-    tera_ctx.add("name", &"Alice".to_owned());
-    tera_ctx.add("text", &"Welcome to the wonderland".to_owned());
-
-    let rendered = ctx.render("index.html", &tera_ctx);
-    ctx.finish(rendered)
+fn hello(ctx: Context) -> AsyncResult {
+    let rendered = ctx.render("index.html", &json!({ "text": "Hello from the form!" }));
+    ctx.respond(rendered)
 }
 
 fn main() {
-    let tera = TeraMiddleware::new();
-    let router = Router::default().with_route(Get, "/", index);
-    let susanoo = Susanoo::new(chain!(tera, router));
+    let router = Router::default()
+        .with_route(Get, "/", index)
+        .with_route(Post, "/", hello);
+    let app = Csrf::new(chain!(current_user, router));
+    let susanoo = Susanoo::new(app).with_state(load_templates());
     let server = susanoo.into_server("0.0.0.0:4000").unwrap();
 
     server.run().unwrap();
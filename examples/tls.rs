@@ -0,0 +1,22 @@
+extern crate susanoo;
+
+use susanoo::{Context, Susanoo, AsyncResult, Router, TlsConfig};
+use susanoo::contrib::hyper::{Get, StatusCode, Response};
+
+fn hello(ctx: Context) -> AsyncResult {
+    let body = format!(
+        "Hello, world (secure: {}, SNI: {:?})",
+        ctx.conn.is_secure(),
+        ctx.conn.sni_hostname()
+    );
+    let res = Response::new().with_status(StatusCode::Ok).with_body(body);
+    ctx.finish(res)
+}
+
+fn main() {
+    let tls = TlsConfig::from_pem_files("cert.pem", "key.pem").unwrap();
+    let router = Router::default().with_route(Get, "/", hello);
+    let susanoo = Susanoo::new(router);
+    let server = susanoo.into_tls_server("0.0.0.0:4443", tls).unwrap();
+    server.run().unwrap();
+}
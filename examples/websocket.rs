@@ -0,0 +1,25 @@
+extern crate susanoo;
+
+use susanoo::{Context, Susanoo, AsyncResult, Router, Message};
+use susanoo::contrib::futures::{Future, Stream, Sink};
+use susanoo::contrib::hyper::Get;
+
+fn echo(ctx: Context) -> AsyncResult {
+    ctx.websocket(|ws| {
+        let (sink, stream) = ws.split();
+        let replies = stream.filter(|message| match *message {
+            Message::Text(_) | Message::Binary(_) => true,
+            _ => false,
+        });
+        sink.send_all(replies).map(|_| ()).map_err(|err| {
+            eprintln!("websocket error: {}", err);
+        })
+    })
+}
+
+fn main() {
+    let router = Router::default().with_route(Get, "/echo", echo);
+    let susanoo = Susanoo::new(router);
+    let server = susanoo.into_server("0.0.0.0:4000").unwrap();
+    server.run().unwrap();
+}
@@ -0,0 +1,470 @@
+//! Access logging.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use futures::{Async, Future, Poll, Stream};
+use hyper::{Body, Chunk, Error as HyperError, HttpVersion, Method, Response, StatusCode};
+use hyper::header::{ContentLength, Referer, UserAgent};
+use tokio_core::reactor::Handle;
+
+use context::Context;
+use middleware::Middleware;
+use request_id::RequestId;
+use result::{AsyncResult, Failure};
+use streaming::spawn_body;
+
+
+/// The format of access log entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Common Log Format.
+    Common,
+    /// Combined Log Format (Common Log Format with `Referer` and `User-Agent`).
+    Combined,
+    /// A JSON object per line.
+    Json,
+}
+
+
+/// A middleware which writes an access log entry for each request handled
+/// by the inner middleware.
+///
+/// The entry is written when the body of the response has been sent (or the connection
+/// is closed). The size is the number of bytes of the body, and the latency is the time
+/// until the inner middleware has finished. If the request is not handled on the event
+/// loop of `Server`, the entry is written immediately, with the size from `Content-Length`.
+///
+/// If the inner middleware ends with a `Failure`, the entry is written immediately with
+/// the error, and the status and `Content-Length` of the response of the failure. The
+/// response sent by the error handler of `Susanoo` is not seen by this middleware.
+pub struct AccessLog<M> {
+    inner: M,
+    format: LogFormat,
+    sink: Arc<Mutex<Box<Write + Send>>>,
+}
+
+impl<M: Middleware> AccessLog<M> {
+    /// Creates an access logger which writes entries of the inner middleware
+    /// to the standard output, in Combined Log Format.
+    pub fn new(inner: M) -> Self {
+        AccessLog {
+            inner,
+            format: LogFormat::Combined,
+            sink: Arc::new(Mutex::new(Box::new(io::stdout()))),
+        }
+    }
+
+    /// Set the format of log entries.
+    pub fn with_format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set the destination of log entries.
+    pub fn with_sink<W: Write + Send + 'static>(mut self, sink: W) -> Self {
+        self.sink = Arc::new(Mutex::new(Box::new(sink)));
+        self
+    }
+
+    fn log<F>(&self, ctx: Context, f: F) -> AsyncResult
+    where
+        F: FnOnce(&M, Context) -> AsyncResult,
+    {
+        let start = Instant::now();
+        let conn = ctx.conn.clone();
+        let mut entry = Entry {
+            record: Record::new(&ctx),
+            format: self.format,
+            sink: self.sink.clone(),
+        };
+
+        f(&self.inner, ctx)
+            .then(move |mut result| {
+                let res = match result {
+                    Ok(ref mut ctx) => {
+                        entry.record.set_context(ctx);
+                        ctx.res.as_mut()
+                    }
+                    Err(ref failure) => {
+                        entry.record.set_failure(failure);
+                        None
+                    }
+                };
+                entry.record.latency = start.elapsed();
+                match (res, conn.remote().and_then(|remote| remote.handle())) {
+                    (Some(res), Some(handle)) => count_body(res, entry, &handle),
+                    (_, _) => entry.write(),
+                }
+                result
+            })
+            .boxed()
+    }
+}
+
+impl<M: Middleware> Middleware for AccessLog<M> {
+    fn call(&self, ctx: Context) -> AsyncResult {
+        self.log(ctx, |inner, ctx| inner.call(ctx))
+    }
+
+    fn after(&self, ctx: Context) -> AsyncResult {
+        self.log(ctx, |inner, ctx| inner.after(ctx))
+    }
+}
+
+
+/// Replaces the body of the response with the one which counts its bytes,
+/// and writes the entry when it is dropped.
+fn count_body(res: &mut Response, mut entry: Entry, handle: &Handle) {
+    if res.body_ref().map_or(true, Body::is_empty) {
+        entry.record.size = Some(0);
+        return entry.write();
+    }
+    let status = res.status();
+    let headers = res.headers().clone();
+    let counted = CountedBody {
+        body: mem::replace(res, Response::new()).body(),
+        size: 0,
+        entry: Some(entry),
+    };
+    *res = Response::new()
+        .with_status(status)
+        .with_headers(headers)
+        .with_body(spawn_body(handle, counted));
+}
+
+struct CountedBody {
+    body: Body,
+    size: u64,
+    entry: Option<Entry>,
+}
+
+impl Stream for CountedBody {
+    type Item = Chunk;
+    type Error = HyperError;
+
+    fn poll(&mut self) -> Poll<Option<Chunk>, HyperError> {
+        let chunk = self.body.poll()?;
+        if let Async::Ready(Some(ref chunk)) = chunk {
+            self.size += chunk.len() as u64;
+        }
+        Ok(chunk)
+    }
+}
+
+impl Drop for CountedBody {
+    fn drop(&mut self) {
+        if let Some(mut entry) = self.entry.take() {
+            entry.record.size = Some(self.size);
+            entry.write();
+        }
+    }
+}
+
+
+struct Entry {
+    record: Record,
+    format: LogFormat,
+    sink: Arc<Mutex<Box<Write + Send>>>,
+}
+
+impl Entry {
+    fn write(self) {
+        if let Ok(mut sink) = self.sink.lock() {
+            let _ = writeln!(sink, "{}", self.record.display(self.format));
+        }
+    }
+}
+
+
+struct Record {
+    time: SystemTime,
+    method: Method,
+    uri: String,
+    version: HttpVersion,
+    peer: Option<IpAddr>,
+    user_agent: Option<String>,
+    referer: Option<String>,
+    status: StatusCode,
+    size: Option<u64>,
+    latency: Duration,
+    request_id: Option<String>,
+    error: Option<String>,
+}
+
+impl Record {
+    fn new(ctx: &Context) -> Self {
+        Record {
+            time: SystemTime::now(),
+            method: ctx.req.method.clone(),
+            uri: ctx.req.uri.to_string(),
+            version: ctx.req.http_version,
+            peer: ctx.client_ip(),
+            user_agent: ctx.req.headers.get::<UserAgent>().map(|ua| ua.to_string()),
+            referer: ctx.req.headers.get::<Referer>().map(|r| r.to_string()),
+            status: StatusCode::NotFound,
+            size: None,
+            latency: Duration::from_secs(0),
+            request_id: ctx.ext.get::<RequestId>().map(|id| id.0.clone()),
+            error: None,
+        }
+    }
+
+    fn set_context(&mut self, ctx: &Context) {
+        if let Some(ref res) = ctx.res {
+            self.set_response(res);
+        }
+        if let Some(id) = ctx.ext.get::<RequestId>() {
+            self.request_id = Some(id.0.clone());
+        }
+    }
+
+    fn set_failure(&mut self, failure: &Failure) {
+        self.set_response(&failure.response);
+        if let Some(id) = failure.ext.get::<RequestId>() {
+            self.request_id = Some(id.0.clone());
+        }
+        self.error = Some(failure.err.to_string());
+    }
+
+    fn set_response(&mut self, res: &Response) {
+        self.status = res.status();
+        self.size = res.headers().get::<ContentLength>().map(|len| len.0);
+    }
+
+    fn display(&self, format: LogFormat) -> Display {
+        Display {
+            record: self,
+            format,
+        }
+    }
+}
+
+
+struct Display<'a> {
+    record: &'a Record,
+    format: LogFormat,
+}
+
+impl<'a> fmt::Display for Display<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let r = self.record;
+        match self.format {
+            LogFormat::Common | LogFormat::Combined => {
+                write!(
+                    f,
+                    "{} - - [{}] \"{} {} {}\" {} ",
+                    opt_or_dash(r.peer.as_ref()),
+                    ClfTime(r.time),
+                    r.method,
+                    r.uri,
+                    r.version,
+                    r.status.as_u16()
+                )?;
+                match r.size {
+                    Some(size) => write!(f, "{}", size)?,
+                    None => write!(f, "-")?,
+                }
+                if self.format == LogFormat::Combined {
+                    write!(
+                        f,
+                        " \"{}\" \"{}\"",
+                        opt_or_dash(r.referer.as_ref()).replace('"', "\\\""),
+                        opt_or_dash(r.user_agent.as_ref()).replace('"', "\\\"")
+                    )?;
+                }
+                Ok(())
+            }
+            LogFormat::Json => {
+                write!(f, "{{\"time\":\"{}\"", Rfc3339Time(r.time))?;
+                write!(f, ",\"method\":{}", Json(&r.method.to_string()))?;
+                write!(f, ",\"uri\":{}", Json(&r.uri))?;
+                write!(f, ",\"version\":\"{}\"", r.version)?;
+                write!(f, ",\"status\":{}", r.status.as_u16())?;
+                match r.size {
+                    Some(size) => write!(f, ",\"size\":{}", size)?,
+                    None => write!(f, ",\"size\":null")?,
+                }
+                write!(
+                    f,
+                    ",\"latency_ms\":{:.3}",
+                    r.latency.as_secs() as f64 * 1e3 + f64::from(r.latency.subsec_nanos()) / 1e6
+                )?;
+                write!(f, ",\"peer\":{}", JsonOpt(r.peer.map(|p| p.to_string()).as_ref()))?;
+                write!(f, ",\"user_agent\":{}", JsonOpt(r.user_agent.as_ref()))?;
+                write!(f, ",\"referer\":{}", JsonOpt(r.referer.as_ref()))?;
+                write!(f, ",\"request_id\":{}", JsonOpt(r.request_id.as_ref()))?;
+                if let Some(ref error) = r.error {
+                    write!(f, ",\"error\":{}", Json(error))?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn opt_or_dash<T: ToString>(val: Option<&T>) -> String {
+    val.map(|v| v.to_string()).unwrap_or_else(|| "-".to_owned())
+}
+
+
+/// Formats a time as `10/Oct/2000:13:55:36 +0000`, in UTC.
+struct ClfTime(SystemTime);
+
+impl fmt::Display for ClfTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const MONTHS: [&str; 12] = [
+            "Jan",
+            "Feb",
+            "Mar",
+            "Apr",
+            "May",
+            "Jun",
+            "Jul",
+            "Aug",
+            "Sep",
+            "Oct",
+            "Nov",
+            "Dec",
+        ];
+        let (year, month, day, hour, min, sec) = utc(self.0);
+        write!(
+            f,
+            "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+            day,
+            MONTHS[(month - 1) as usize],
+            year,
+            hour,
+            min,
+            sec
+        )
+    }
+}
+
+/// Formats a time as `2000-10-10T13:55:36Z` (RFC 3339).
+struct Rfc3339Time(SystemTime);
+
+impl fmt::Display for Rfc3339Time {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (year, month, day, hour, min, sec) = utc(self.0);
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year,
+            month,
+            day,
+            hour,
+            min,
+            sec
+        )
+    }
+}
+
+fn utc(time: SystemTime) -> (i64, u32, u32, u64, u64, u64) {
+    let secs = time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs = secs % 86_400;
+    (year, month, day, secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+/// Converts days since the Unix epoch into a date of the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+
+/// Formats a string as a JSON string literal.
+struct Json<'a>(&'a str);
+
+impl<'a> fmt::Display for Json<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"")?;
+        for c in self.0.chars() {
+            match c {
+                '"' => write!(f, "\\\"")?,
+                '\\' => write!(f, "\\\\")?,
+                '\n' => write!(f, "\\n")?,
+                '\r' => write!(f, "\\r")?,
+                '\t' => write!(f, "\\t")?,
+                c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                c => write!(f, "{}", c)?,
+            }
+        }
+        write!(f, "\"")
+    }
+}
+
+struct JsonOpt<'a>(Option<&'a String>);
+
+impl<'a> fmt::Display for JsonOpt<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(s) => Json(s).fmt(f),
+            None => write!(f, "null"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use hyper::{Request, Get};
+    use super::*;
+
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn count_body_size() {
+        let ctx = Context::from_hyper(Request::new(Get, "/".parse().unwrap()));
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let mut record = Record::new(&ctx);
+        record.status = StatusCode::Ok;
+        let body = CountedBody {
+            body: Body::from("Hello, world"),
+            size: 0,
+            entry: Some(Entry {
+                record,
+                format: LogFormat::Common,
+                sink: Arc::new(Mutex::new(Box::new(Buffer(buffer.clone())))),
+            }),
+        };
+
+        let chunks = body.collect().wait().unwrap();
+        assert_eq!(chunks.len(), 1);
+        let entry = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+        assert!(entry.ends_with("\"GET / HTTP/1.1\" 200 12\n"), "{}", entry);
+    }
+
+    #[test]
+    fn format_time() {
+        let time = UNIX_EPOCH + Duration::from_secs(971_186_136);
+        assert_eq!(ClfTime(time).to_string(), "10/Oct/2000:13:55:36 +0000");
+        assert_eq!(Rfc3339Time(time).to_string(), "2000-10-10T13:55:36Z");
+    }
+}
@@ -0,0 +1,71 @@
+use std::sync::Mutex;
+use futures::{future, Future};
+use futures::future::BoxFuture;
+use futures_cpupool::{Builder, CpuPool};
+use result::Failure;
+
+
+/// A thread pool for blocking work, started on first use.
+///
+/// The number of threads defaults to the number of CPUs.
+pub(crate) struct BlockingPool {
+    size: Option<usize>,
+    pool: Mutex<Option<CpuPool>>,
+}
+
+impl BlockingPool {
+    pub(crate) fn new(size: Option<usize>) -> Self {
+        BlockingPool {
+            size,
+            pool: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn get(&self) -> CpuPool {
+        let mut pool = match self.pool.lock() {
+            Ok(pool) => pool,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let size = self.size;
+        pool.get_or_insert_with(|| {
+            let mut builder = Builder::new();
+            if let Some(size) = size {
+                builder.pool_size(size);
+            }
+            builder.name_prefix("susanoo-blocking-").create()
+        }).clone()
+    }
+}
+
+
+/// Runs a blocking function on the pool, or in place if there is no pool.
+pub(crate) fn spawn<F, T, E>(pool: Option<&BlockingPool>, f: F) -> BoxFuture<T, Failure>
+where
+    F: FnOnce() -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: Into<Failure> + Send + 'static,
+{
+    match pool {
+        Some(pool) => pool.get().spawn_fn(f).map_err(Into::into).boxed(),
+        None => future::lazy(f).map_err(Into::into).boxed(),
+    }
+}
+
+/// Runs a blocking function on the pool without waiting for it, or in place if there is no pool.
+///
+/// Unlike `spawn()`, the function runs even if nothing waits for it (e.g. cleanup in `Drop`).
+#[cfg(feature = "pool")]
+pub(crate) fn spawn_detached<F>(pool: Option<&BlockingPool>, f: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    match pool {
+        Some(pool) => pool.get()
+            .spawn_fn(move || -> Result<(), ()> {
+                f();
+                Ok(())
+            })
+            .forget(),
+        None => f(),
+    }
+}
@@ -0,0 +1,233 @@
+//! Limiting of concurrent requests, and load shedding.
+
+use std::collections::VecDeque;
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use futures::{future, Future};
+use futures::future::{BoxFuture, Either};
+use futures::sync::oneshot;
+use hyper::{Response, StatusCode};
+use tokio_core::reactor::{Handle, Timeout as ReactorTimeout};
+
+use context::Context;
+use middleware::Middleware;
+use result::{AsyncResult, Failure};
+
+
+/// The error returned when a request is shed because of overload.
+#[derive(Debug)]
+pub struct Overloaded;
+
+impl fmt::Display for Overloaded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "overloaded")
+    }
+}
+
+impl StdError for Overloaded {
+    fn description(&self) -> &str {
+        "overloaded"
+    }
+}
+
+
+/// A middleware which limits the number of requests processed by the inner
+/// middleware at once.
+///
+/// Requests exceeding the limit wait in a bounded queue, in order of arrival.
+/// They are rejected with `503 Service Unavailable` if the queue is full, or if
+/// they have waited longer than the queue timeout.
+///
+/// Wrap a `Router` to apply the limit globally, or the middleware of a route
+/// to apply it per route.
+pub struct ConcurrencyLimit<M> {
+    inner: Arc<M>,
+    semaphore: Arc<Semaphore>,
+    max_queue: usize,
+    queue_timeout: Option<Duration>,
+}
+
+impl<M: Middleware> ConcurrencyLimit<M> {
+    /// Creates a middleware which allows `max_concurrent` requests at once, without queueing.
+    pub fn new(inner: M, max_concurrent: usize) -> Self {
+        ConcurrencyLimit {
+            inner: Arc::new(inner),
+            semaphore: Arc::new(Semaphore::new(max_concurrent)),
+            max_queue: 0,
+            queue_timeout: None,
+        }
+    }
+
+    /// Set the maximum number of waiting requests, and how long each of them may wait.
+    ///
+    /// The timeout is driven by the event loop of `Server`; if the request
+    /// was not accepted by `Server`, it waits without a timeout.
+    pub fn with_queue(mut self, max_len: usize, timeout: Duration) -> Self {
+        self.max_queue = max_len;
+        self.queue_timeout = Some(timeout);
+        self
+    }
+
+    /// Returns the number of requests being processed by the inner middleware.
+    pub fn in_flight(&self) -> usize {
+        self.semaphore.lock().in_flight
+    }
+}
+
+impl<M: Middleware> Middleware for ConcurrencyLimit<M> {
+    fn call(&self, ctx: Context) -> AsyncResult {
+        let permit = self.semaphore
+            .acquire(ctx.handle(), self.max_queue, self.queue_timeout, overloaded);
+        let inner = self.inner.clone();
+        permit
+            .and_then(move |permit| {
+                inner.call(ctx).then(move |result| {
+                    drop(permit);
+                    result
+                })
+            })
+            .boxed()
+    }
+
+    fn after(&self, ctx: Context) -> AsyncResult {
+        self.inner.after(ctx)
+    }
+}
+
+fn overloaded() -> Failure {
+    Failure::from(Overloaded).with_response(
+        Response::new()
+            .with_status(StatusCode::ServiceUnavailable)
+            .with_body("Service Unavailable"),
+    )
+}
+
+
+/// A counting semaphore whose waiters are queued in order of arrival.
+pub(crate) struct Semaphore {
+    max_concurrent: usize,
+    state: Mutex<State>,
+}
+
+struct State {
+    in_flight: usize,
+    waiters: VecDeque<oneshot::Sender<Permit>>,
+}
+
+enum Acquire {
+    Ready(Permit),
+    Queued(oneshot::Receiver<Permit>),
+    Full,
+}
+
+impl Semaphore {
+    pub(crate) fn new(max_concurrent: usize) -> Self {
+        Semaphore {
+            max_concurrent,
+            state: Mutex::new(State {
+                in_flight: 0,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<State> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Acquires a permit, waiting in the queue of at most `max_queue` waiters for `timeout`.
+    ///
+    /// The timeout is driven by the event loop of `handle`; without it, the waiter waits
+    /// without a timeout. `rejected` creates the failure returned if the queue is full
+    /// or the timeout has elapsed.
+    pub(crate) fn acquire(
+        self: &Arc<Self>,
+        handle: Option<Handle>,
+        max_queue: usize,
+        timeout: Option<Duration>,
+        rejected: fn() -> Failure,
+    ) -> BoxFuture<Permit, Failure> {
+        let rx = match self.try_acquire(max_queue) {
+            Acquire::Ready(permit) => return future::ok(permit).boxed(),
+            Acquire::Queued(rx) => rx,
+            Acquire::Full => return future::err(rejected()).boxed(),
+        };
+        let waiting = rx.map_err(move |_| rejected());
+        match (handle, timeout) {
+            (Some(handle), Some(timeout)) => {
+                let timeout = match ReactorTimeout::new(timeout, &handle) {
+                    Ok(timeout) => timeout,
+                    Err(err) => return future::err(err.into()).boxed(),
+                };
+                waiting
+                    .select2(timeout)
+                    .then(move |result| match result {
+                        Ok(Either::A((permit, _))) => Ok(permit),
+                        Ok(Either::B(_)) => Err(rejected()),
+                        Err(Either::A((failure, _))) => Err(failure),
+                        Err(Either::B((err, _))) => Err(err.into()),
+                    })
+                    .boxed()
+            }
+            _ => waiting.boxed(),
+        }
+    }
+
+    fn try_acquire(self: &Arc<Self>, max_queue: usize) -> Acquire {
+        let mut state = self.lock();
+        if state.in_flight < self.max_concurrent {
+            state.in_flight += 1;
+            return Acquire::Ready(Permit { semaphore: Some(self.clone()) });
+        }
+
+        // Waiters which have timed out (or whose connection has been closed) still occupy the queue.
+        state.waiters.retain(|tx| !tx.is_canceled());
+        if state.waiters.len() < max_queue {
+            let (tx, rx) = oneshot::channel();
+            state.waiters.push_back(tx);
+            Acquire::Queued(rx)
+        } else {
+            Acquire::Full
+        }
+    }
+}
+
+
+/// A slot of concurrent processing, which is handed over to the next waiter when dropped.
+pub(crate) struct Permit {
+    semaphore: Option<Arc<Semaphore>>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let semaphore = match self.semaphore.take() {
+            Some(semaphore) => semaphore,
+            None => return,
+        };
+        loop {
+            let waiter = {
+                let mut state = semaphore.lock();
+                match state.waiters.pop_front() {
+                    Some(waiter) => waiter,
+                    None => {
+                        state.in_flight -= 1;
+                        return;
+                    }
+                }
+            };
+            match waiter.send(Permit { semaphore: Some(semaphore.clone()) }) {
+                Ok(()) => return,
+                // The waiter has gone away; the slot is still ours, so try the next one.
+                Err(mut permit) => {
+                    permit.semaphore = None;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Entity tags and conditional requests.
+
+use std::error::Error as StdError;
+use std::fmt;
+
+use base64;
+use futures::{future, Future, Stream};
+use hyper::{Body, Method, Response, StatusCode};
+use hyper::header::{ContentLength, ContentType, ETag, EntityTag, Headers, HttpDate, IfMatch,
+                    IfModifiedSince, IfNoneMatch, IfUnmodifiedSince, LastModified};
+use sha1::Sha1;
+
+use context::Context;
+use middleware::Middleware;
+use result::{AsyncResult, Failure};
+use streaming::StreamedBody;
+
+
+/// The error returned when a precondition of the request (e.g. `If-Match`) is not met.
+#[derive(Debug)]
+pub struct PreconditionFailed;
+
+impl fmt::Display for PreconditionFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "precondition of the request is not met")
+    }
+}
+
+impl StdError for PreconditionFailed {
+    fn description(&self) -> &str {
+        "precondition failed"
+    }
+}
+
+
+/// A middleware which evaluates conditional requests against the responses of the inner middleware.
+///
+/// If the response has no `ETag`, it is computed from the body. Streamed bodies are left
+/// untouched. Successful responses to `GET` and `HEAD` are turned into `304 Not Modified`
+/// if `If-None-Match` or `If-Modified-Since` matches, and into `412 Precondition Failed`
+/// if `If-Match` or `If-Unmodified-Since` does not.
+///
+/// The preconditions of other methods are not evaluated, since the changes have already been
+/// applied when the response is available, and it describes the new state of the resource.
+/// Handlers of unsafe methods (e.g. `PUT`) must check `If-Match` themselves before writing.
+pub struct Conditional<M> {
+    inner: M,
+    weak: bool,
+}
+
+impl<M: Middleware> Conditional<M> {
+    /// Creates a middleware which computes strong entity tags.
+    pub fn new(inner: M) -> Self {
+        Conditional { inner, weak: false }
+    }
+
+    /// Set whether the computed entity tags are weak (e.g. when the body is compressed later).
+    pub fn with_weak(mut self, weak: bool) -> Self {
+        self.weak = weak;
+        self
+    }
+
+    fn check<F>(&self, ctx: Context, f: F) -> AsyncResult
+    where
+        F: FnOnce(&M, Context) -> AsyncResult,
+    {
+        let weak = self.weak;
+        f(&self.inner, ctx)
+            .and_then(move |mut ctx| {
+                let streamed = ctx.ext.contains::<StreamedBody>();
+                let res = match ctx.res.take() {
+                    Some(res) => res,
+                    None => return future::ok(ctx).boxed(),
+                };
+                if !res.status().is_success() || streamed {
+                    ctx.res = Some(res);
+                    return future::ok(ctx).boxed();
+                }
+                with_etag(res, weak)
+                    .and_then(move |res| {
+                        let res = {
+                            let etag = res.headers().get::<ETag>().map(|&ETag(ref tag)| tag);
+                            let last_modified = res.headers()
+                                .get::<LastModified>()
+                                .map(|&LastModified(date)| date);
+                            match evaluate(&ctx.req.method, &ctx.req.headers, etag, last_modified) {
+                                Precondition::Proceed => res,
+                                Precondition::NotModified => not_modified(res),
+                                Precondition::Failed => {
+                                    return Err(Failure::from(PreconditionFailed).with_response(
+                                        Response::new()
+                                            .with_status(StatusCode::PreconditionFailed)
+                                            .with_body("Precondition Failed"),
+                                    ))
+                                }
+                            }
+                        };
+                        ctx.res = Some(res);
+                        Ok(ctx)
+                    })
+                    .boxed()
+            })
+            .boxed()
+    }
+}
+
+impl<M: Middleware> Middleware for Conditional<M> {
+    fn call(&self, ctx: Context) -> AsyncResult {
+        self.check(ctx, |inner, ctx| inner.call(ctx))
+    }
+
+    fn after(&self, ctx: Context) -> AsyncResult {
+        self.check(ctx, |inner, ctx| inner.after(ctx))
+    }
+}
+
+
+/// Sets `ETag` computed from the body, unless the response already has one.
+fn with_etag(res: Response, weak: bool) -> Box<Future<Item = Response, Error = Failure> + Send> {
+    if res.headers().has::<ETag>() {
+        return Box::new(future::ok(res));
+    }
+    let mut head = Response::new()
+        .with_status(res.status())
+        .with_headers(res.headers().clone());
+    Box::new(res.body().concat2().map_err(Failure::from).map(move |body| {
+        let mut sha1 = Sha1::new();
+        sha1.update(&body);
+        let tag = base64::encode_config(&sha1.digest().bytes(), base64::URL_SAFE_NO_PAD);
+        head.headers_mut().set(ETag(EntityTag::new(weak, tag)));
+        head.headers_mut().set(ContentLength(body.len() as u64));
+        head.with_body(Body::from(body))
+    }))
+}
+
+fn not_modified(mut res: Response) -> Response {
+    res.headers_mut().remove::<ContentLength>();
+    res.headers_mut().remove::<ContentType>();
+    res.with_status(StatusCode::NotModified).with_body(Body::empty())
+}
+
+
+#[derive(Debug, PartialEq)]
+enum Precondition {
+    Proceed,
+    NotModified,
+    Failed,
+}
+
+/// Evaluates the preconditions of the request in the order of RFC 7232, section 6.
+///
+/// Only `GET` and `HEAD` are evaluated; the others always proceed.
+fn evaluate(
+    method: &Method,
+    headers: &Headers,
+    etag: Option<&EntityTag>,
+    last_modified: Option<HttpDate>,
+) -> Precondition {
+    if *method != Method::Get && *method != Method::Head {
+        return Precondition::Proceed;
+    }
+
+    match headers.get::<IfMatch>() {
+        Some(&IfMatch::Any) if etag.is_none() => return Precondition::Failed,
+        Some(&IfMatch::Items(ref tags)) => {
+            let matched = etag.map_or(false, |etag| tags.iter().any(|tag| tag.strong_eq(etag)));
+            if !matched {
+                return Precondition::Failed;
+            }
+        }
+        Some(&IfMatch::Any) => {}
+        None => if let (Some(&IfUnmodifiedSince(since)), Some(modified)) =
+            (headers.get::<IfUnmodifiedSince>(), last_modified)
+        {
+            if modified > since {
+                return Precondition::Failed;
+            }
+        },
+    }
+
+    match headers.get::<IfNoneMatch>() {
+        Some(&IfNoneMatch::Any) => Precondition::NotModified,
+        Some(&IfNoneMatch::Items(ref tags)) => {
+            if etag.map_or(false, |etag| tags.iter().any(|tag| tag.weak_eq(etag))) {
+                Precondition::NotModified
+            } else {
+                Precondition::Proceed
+            }
+        }
+        None => match (headers.get::<IfModifiedSince>(), last_modified) {
+            (Some(&IfModifiedSince(since)), Some(modified)) if modified <= since => {
+                Precondition::NotModified
+            }
+            _ => Precondition::Proceed,
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evaluate_preconditions() {
+        let etag = EntityTag::strong("abc".to_owned());
+        let eval = |method: Method, name: &'static str, value: &str| {
+            let mut headers = Headers::new();
+            headers.set_raw(name, value.to_owned());
+            evaluate(&method, &headers, Some(&etag), None)
+        };
+
+        assert_eq!(eval(Method::Get, "If-None-Match", "\"abc\""), Precondition::NotModified);
+        assert_eq!(eval(Method::Get, "If-None-Match", "W/\"abc\""), Precondition::NotModified);
+        assert_eq!(eval(Method::Get, "If-None-Match", "\"xyz\""), Precondition::Proceed);
+        assert_eq!(eval(Method::Get, "If-Match", "W/\"abc\""), Precondition::Failed);
+        assert_eq!(eval(Method::Get, "If-Match", "\"xyz\""), Precondition::Failed);
+
+        // The client of a conditional write sends the tag of the old representation.
+        assert_eq!(eval(Method::Put, "If-Match", "\"old\""), Precondition::Proceed);
+        assert_eq!(eval(Method::Delete, "If-None-Match", "*"), Precondition::Proceed);
+    }
+}
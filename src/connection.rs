@@ -0,0 +1,89 @@
+use std::net::SocketAddr;
+use tokio_core::reactor::Remote;
+
+
+/// Information about the transport a request was received on.
+///
+/// A `Connection` is created once per accepted connection and shared
+/// between all of the requests sent over it.
+#[derive(Debug, Clone, Default)]
+pub struct Connection {
+    id: usize,
+    peer_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
+    secure: bool,
+    sni_hostname: Option<String>,
+    remote: Option<Remote>,
+}
+
+impl Connection {
+    /// Creates the information of a plain-text HTTP connection.
+    pub(crate) fn plain(id: usize) -> Self {
+        Connection {
+            id,
+            ..Connection::default()
+        }
+    }
+
+    /// Creates the information of a connection secured by TLS.
+    #[cfg(feature = "tls")]
+    pub(crate) fn secure(id: usize, sni_hostname: Option<String>) -> Self {
+        Connection {
+            id,
+            secure: true,
+            sni_hostname,
+            ..Connection::default()
+        }
+    }
+
+    pub(crate) fn with_addrs(mut self, peer_addr: SocketAddr, local_addr: Option<SocketAddr>) -> Self {
+        self.peer_addr = Some(peer_addr);
+        self.local_addr = local_addr;
+        self
+    }
+
+    pub(crate) fn with_remote(mut self, remote: Remote) -> Self {
+        self.remote = Some(remote);
+        self
+    }
+
+    pub(crate) fn remote(&self) -> Option<&Remote> {
+        self.remote.as_ref()
+    }
+
+    /// Returns the identifier of the connection.
+    ///
+    /// The identifier is unique among the connections accepted by a `Susanoo` instance.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Returns the socket address of the remote peer.
+    ///
+    /// If the server is behind a reverse proxy, this is the address of the proxy.
+    /// Use `TrustedProxies` to resolve the address of the original client.
+    ///
+    /// This value is `None` if the connection was not accepted by `Server`.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Returns the local socket address the connection was accepted on.
+    ///
+    /// This value is `None` if the connection was not accepted by `Server`.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Returns whether the connection was secured by TLS.
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    /// Returns the server name the client requested via SNI, if any.
+    ///
+    /// This value is always `None` for plain-text connections.
+    pub fn sni_hostname(&self) -> Option<&str> {
+        self.sni_hostname.as_ref().map(|s| s.as_str())
+    }
+}
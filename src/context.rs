@@ -1,7 +1,15 @@
+use std::any::Any;
+use std::sync::Arc;
 use hyper::{Request as HyperRequest, Response};
 use typemap::SendMap;
 use futures::{future, Future};
-use result::AsyncResult;
+use futures::future::BoxFuture;
+use tokio_core::reactor::Handle;
+use blocking::{self, BlockingPool};
+use connection::Connection;
+use responder::Responder;
+use result::{AsyncResult, Failure};
+use state::{AppState, MissingState};
 use request::Request;
 
 
@@ -13,17 +21,75 @@ pub struct Context {
     pub req: Request,
     pub ext: SendMap,
     pub res: Option<Response>,
+    pub conn: Arc<Connection>,
+    blocking: Option<Arc<BlockingPool>>,
+    state: Arc<AppState>,
 }
 
 impl Context {
     pub fn from_hyper(req: HyperRequest) -> Self {
+        Context::new(req, Arc::new(Connection::default()))
+    }
+
+    pub(crate) fn new(req: HyperRequest, conn: Arc<Connection>) -> Self {
         Context {
             req: req.into(),
             ext: SendMap::custom(),
             res: None,
+            conn,
+            blocking: None,
+            state: Arc::new(AppState::default()),
         }
     }
 
+    pub(crate) fn with_blocking_pool(mut self, pool: Arc<BlockingPool>) -> Self {
+        self.blocking = Some(pool);
+        self
+    }
+
+    pub(crate) fn with_state(mut self, state: Arc<AppState>) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// Returns a reference to the application state of type `T`,
+    /// registered by `Susanoo::with_state()`.
+    pub fn state<T: Any + Send + Sync>(&self) -> Result<&T, MissingState> {
+        self.state.get()
+    }
+
+    /// Returns a shared pointer to the application state of type `T`,
+    /// which can be moved into futures or `spawn_blocking()`.
+    pub fn state_arc<T: Any + Send + Sync>(&self) -> Result<Arc<T>, MissingState> {
+        self.state.get_arc()
+    }
+
+    /// Returns a handle to the event loop which drives this request.
+    ///
+    /// This returns `None` if the request was not accepted by `Server`.
+    pub fn handle(&self) -> Option<Handle> {
+        self.conn.remote().and_then(|remote| remote.handle())
+    }
+
+    /// Runs a blocking function (e.g. a query to a database) on a thread pool,
+    /// off the event loop, and returns a future of its result.
+    ///
+    /// The pool is configured by `Susanoo::with_blocking_threads()`.
+    /// If the request was not handled by `Susanoo`, the function runs in place.
+    pub fn spawn_blocking<F, T, E>(&self, f: F) -> BoxFuture<T, Failure>
+    where
+        F: FnOnce() -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: Into<Failure> + Send + 'static,
+    {
+        blocking::spawn(self.blocking.as_ref().map(|pool| &**pool), f)
+    }
+
+    #[cfg(feature = "pool")]
+    pub(crate) fn blocking_pool(&self) -> Option<Arc<BlockingPool>> {
+        self.blocking.clone()
+    }
+
     pub fn next(self) -> AsyncResult {
         future::ok(self).boxed()
     }
@@ -32,4 +98,16 @@ impl Context {
         self.res = Some(res);
         self.next()
     }
+
+    /// Finishes the context with the response converted from a `Responder`.
+    ///
+    /// ```ignore
+    /// ctx.respond((StatusCode::Created, Json(user)))
+    /// ```
+    pub fn respond<R: Responder>(self, responder: R) -> AsyncResult {
+        match responder.into_response() {
+            Ok(res) => self.finish(res),
+            Err(failure) => future::err(failure).boxed(),
+        }
+    }
 }
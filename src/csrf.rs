@@ -0,0 +1,275 @@
+//! Protection against cross-site request forgery.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::Arc;
+
+use base64;
+use futures::{future, Future, Stream};
+use hyper::{Body, Method, Response, StatusCode};
+use hyper::header::{ContentType, Cookie};
+use hyper::mime;
+use typemap::Key;
+
+use context::Context;
+use extract::parse_query;
+use middleware::Middleware;
+use result::{AsyncResult, Failure};
+use security_headers::random_bytes;
+
+
+/// The maximum size of a form body which `Csrf` reads to find the token.
+pub const FORM_BODY_LIMIT: usize = 1024 * 1024;
+
+
+/// The CSRF token of a request, stored in `ctx.ext` by `Csrf`.
+///
+/// Embed it in forms as a hidden field, or in a `<meta>` element for scripts
+/// which send it in the header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsrfToken(pub String);
+
+impl Key for CsrfToken {
+    type Value = Self;
+}
+
+impl Context {
+    /// Returns the CSRF token of the request, if `Csrf` is applied.
+    pub fn csrf_token(&self) -> Option<&str> {
+        self.ext.get::<CsrfToken>().map(|token| token.0.as_str())
+    }
+}
+
+
+/// The error returned when a request with an unsafe method has no valid CSRF token.
+#[derive(Debug)]
+pub struct CsrfRejected(&'static str);
+
+impl fmt::Display for CsrfRejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CSRF check failed: {}", self.0)
+    }
+}
+
+impl StdError for CsrfRejected {
+    fn description(&self) -> &str {
+        "CSRF check failed"
+    }
+}
+
+
+/// A middleware which protects the inner middleware against CSRF with double-submit cookies.
+///
+/// Each client gets a random token in a cookie, which is also stored in `ctx.ext` as
+/// `CsrfToken`. Requests with `POST`, `PUT`, `PATCH` or `DELETE` must send the same token
+/// in the header, or in the form field of a `application/x-www-form-urlencoded` body.
+/// Otherwise, they are rejected with `403 Forbidden`.
+pub struct Csrf<M> {
+    inner: Arc<M>,
+    cookie_name: String,
+    header_name: String,
+    field_name: String,
+    secure: Option<bool>,
+}
+
+impl<M: Middleware> Csrf<M> {
+    /// Creates a middleware with the cookie and the form field `csrf_token`,
+    /// and the header `X-CSRF-Token`.
+    pub fn new(inner: M) -> Self {
+        Csrf {
+            inner: Arc::new(inner),
+            cookie_name: "csrf_token".to_owned(),
+            header_name: "X-CSRF-Token".to_owned(),
+            field_name: "csrf_token".to_owned(),
+            secure: None,
+        }
+    }
+
+    /// Set the name of the cookie.
+    pub fn with_cookie_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.cookie_name = name.into();
+        self
+    }
+
+    /// Set the name of the header.
+    pub fn with_header_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.header_name = name.into();
+        self
+    }
+
+    /// Set the name of the form field.
+    pub fn with_field_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.field_name = name.into();
+        self
+    }
+
+    /// Set whether the cookie has the `Secure` attribute.
+    ///
+    /// By default, it is set if the connection is secured by TLS.
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.secure = Some(secure);
+        self
+    }
+}
+
+impl<M: Middleware> Middleware for Csrf<M> {
+    fn call(&self, mut ctx: Context) -> AsyncResult {
+        let cookie_token = ctx.req
+            .headers
+            .get::<Cookie>()
+            .and_then(|cookie| cookie.get(&self.cookie_name))
+            .filter(|token| is_valid(token))
+            .map(ToOwned::to_owned);
+        let token = cookie_token.clone().unwrap_or_else(generate);
+        ctx.ext.insert::<CsrfToken>(CsrfToken(token.clone()));
+
+        let set_cookie = match cookie_token {
+            Some(_) => None,
+            None => {
+                let secure = self.secure.unwrap_or_else(|| ctx.conn.is_secure());
+                Some(format!(
+                    "{}={}; Path=/; HttpOnly; SameSite=Strict{}",
+                    self.cookie_name,
+                    token,
+                    if secure { "; Secure" } else { "" }
+                ))
+            }
+        };
+
+        let verified = if is_unsafe(&ctx.req.method) {
+            match cookie_token {
+                Some(token) => verify(ctx, token, &self.header_name, self.field_name.clone()),
+                None => future::err(rejected("missing CSRF cookie")).boxed(),
+            }
+        } else {
+            future::ok(ctx).boxed()
+        };
+
+        let inner = self.inner.clone();
+        verified
+            .and_then(move |ctx| inner.call(ctx))
+            .then(move |result| {
+                let cookie = match set_cookie {
+                    Some(cookie) => cookie,
+                    None => return result,
+                };
+                match result {
+                    Ok(mut ctx) => {
+                        if let Some(ref mut res) = ctx.res {
+                            res.headers_mut().append_raw("Set-Cookie", cookie);
+                        }
+                        Ok(ctx)
+                    }
+                    Err(mut failure) => {
+                        failure.response.headers_mut().append_raw("Set-Cookie", cookie);
+                        Err(failure)
+                    }
+                }
+            })
+            .boxed()
+    }
+
+    fn after(&self, ctx: Context) -> AsyncResult {
+        self.inner.after(ctx)
+    }
+}
+
+
+/// Checks the token in the header, or in the form field of the body.
+fn verify(mut ctx: Context, expected: String, header_name: &str, field_name: String) -> AsyncResult {
+    let submitted = ctx.req
+        .headers
+        .get_raw(header_name)
+        .and_then(|raw| raw.one())
+        .map(|value| value.to_owned());
+    if let Some(submitted) = submitted {
+        return if constant_time_eq(&submitted, expected.as_bytes()) {
+            future::ok(ctx).boxed()
+        } else {
+            future::err(rejected("CSRF token mismatch")).boxed()
+        };
+    }
+
+    let is_form = ctx.req.headers.get::<ContentType>().map_or(false, |ct| {
+        ct.type_() == mime::APPLICATION && ct.subtype() == mime::WWW_FORM_URLENCODED
+    });
+    let body = match ctx.req.take_body() {
+        Some(body) if is_form => body,
+        Some(body) => {
+            ctx.req.set_body(body);
+            return future::err(rejected("missing CSRF token")).boxed();
+        }
+        None => return future::err(rejected("missing CSRF token")).boxed(),
+    };
+
+    body.map_err(Failure::from)
+        .fold(Vec::new(), |mut buf, chunk| {
+            if buf.len() + chunk.len() > FORM_BODY_LIMIT {
+                return Err(Failure::from(CsrfRejected("form body is too large")).with_response(
+                    Response::new()
+                        .with_status(StatusCode::PayloadTooLarge)
+                        .with_body("Payload Too Large"),
+                ));
+            }
+            buf.extend_from_slice(&chunk);
+            Ok(buf)
+        })
+        .and_then(move |buf| {
+            let submitted = parse_query(&String::from_utf8_lossy(&buf))
+                .into_iter()
+                .find(|&(ref name, _)| name.as_ref() == Some(&field_name))
+                .map(|(_, value)| value);
+            // The handler reads the form again.
+            ctx.req.set_body(Body::from(buf));
+            match submitted {
+                Some(ref submitted) if constant_time_eq(submitted.as_bytes(), expected.as_bytes()) => Ok(ctx),
+                Some(_) => Err(rejected("CSRF token mismatch")),
+                None => Err(rejected("missing CSRF token")),
+            }
+        })
+        .boxed()
+}
+
+fn is_unsafe(method: &Method) -> bool {
+    match *method {
+        Method::Post | Method::Put | Method::Patch | Method::Delete => true,
+        _ => false,
+    }
+}
+
+fn rejected(reason: &'static str) -> Failure {
+    Failure::from(CsrfRejected(reason)).with_response(
+        Response::new()
+            .with_status(StatusCode::Forbidden)
+            .with_body("Forbidden"),
+    )
+}
+
+/// Compares the tokens in time independent of the position of the first difference.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn is_valid(token: &str) -> bool {
+    token.len() == 43 && token.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Generates a token from 256 random bits.
+fn generate() -> String {
+    base64::encode_config(&random_bytes(32), base64::URL_SAFE_NO_PAD)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_token_is_valid() {
+        let token = generate();
+        assert!(is_valid(&token));
+        assert!(!is_valid("short"));
+        assert!(constant_time_eq(token.as_bytes(), token.clone().as_bytes()));
+        assert!(!constant_time_eq(token.as_bytes(), generate().as_bytes()));
+    }
+}
@@ -0,0 +1,261 @@
+//! Typed values extracted from a request.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+use futures::{future, Future, Stream};
+use futures::future::BoxFuture;
+use hyper::StatusCode;
+use hyper::header::ContentType;
+use hyper::mime;
+use hyper::server::Response;
+use percent_encoding::percent_decode;
+use serde::de::DeserializeOwned;
+use serde_json;
+use typemap::Key;
+
+use context::Context;
+use regex_pattern::OwnedCaptures;
+use result::Failure;
+
+mod de;
+
+use self::de::Params;
+
+
+/// The maximum size of a request body read by `Json`.
+pub const JSON_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+
+/// The error returned when a value cannot be extracted from a request.
+#[derive(Debug)]
+pub struct ExtractError(String);
+
+impl ExtractError {
+    fn into_failure(self, status: StatusCode) -> Failure {
+        let body = self.0.clone();
+        Failure::from(self).with_response(Response::new().with_status(status).with_body(body))
+    }
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl StdError for ExtractError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+
+/// A type which can be extracted from a request, used as an argument of handlers.
+///
+/// Extraction takes what it needs from the context (e.g. the request body)
+/// synchronously, and may complete asynchronously.
+pub trait FromContext: Sized + Send + 'static {
+    fn from_context(ctx: &mut Context) -> BoxFuture<Self, Failure>;
+}
+
+macro_rules! impl_from_context_for_tuple {
+    ($join:ident; $A:ident, $($T:ident),+) => {
+        impl<$A: FromContext, $($T: FromContext),+> FromContext for ($A, $($T),+) {
+            fn from_context(ctx: &mut Context) -> BoxFuture<Self, Failure> {
+                $A::from_context(ctx).$join($($T::from_context(ctx)),+).boxed()
+            }
+        }
+    }
+}
+
+impl FromContext for () {
+    fn from_context(_: &mut Context) -> BoxFuture<Self, Failure> {
+        future::ok(()).boxed()
+    }
+}
+
+impl<A: FromContext> FromContext for (A,) {
+    fn from_context(ctx: &mut Context) -> BoxFuture<Self, Failure> {
+        A::from_context(ctx).map(|a| (a,)).boxed()
+    }
+}
+
+impl_from_context_for_tuple!(join; A, B);
+impl_from_context_for_tuple!(join3; A, B, C);
+impl_from_context_for_tuple!(join4; A, B, C, D);
+impl_from_context_for_tuple!(join5; A, B, C, D, E);
+
+
+macro_rules! impl_deref {
+    ($name:ident) => {
+        impl<T> Deref for $name<T> {
+            type Target = T;
+            fn deref(&self) -> &T {
+                &self.0
+            }
+        }
+
+        impl<T> DerefMut for $name<T> {
+            fn deref_mut(&mut self) -> &mut T {
+                &mut self.0
+            }
+        }
+    }
+}
+
+
+/// The parameters captured from the path by `Router`.
+///
+/// A single capture group can be extracted as a scalar (e.g. `Path<u32>`),
+/// all groups as a tuple, and named groups as a struct or a map.
+/// The request is rejected with `404 Not Found` if the captures cannot be parsed.
+pub struct Path<T>(pub T);
+
+impl_deref!(Path);
+
+impl<T: DeserializeOwned + Send + 'static> FromContext for Path<T> {
+    fn from_context(ctx: &mut Context) -> BoxFuture<Self, Failure> {
+        let params = ctx.ext
+            .get::<OwnedCaptures>()
+            .map_or_else(Vec::new, |caps| caps.params());
+        let result = T::deserialize(Params(params))
+            .map(Path)
+            .map_err(|err| {
+                ExtractError(format!("invalid path parameters: {}", err)).into_failure(StatusCode::NotFound)
+            });
+        future::result(result).boxed()
+    }
+}
+
+
+/// The parameters in the query string, deserialized as a struct or a map.
+///
+/// The request is rejected with `400 Bad Request` if the query cannot be parsed.
+pub struct Query<T>(pub T);
+
+impl_deref!(Query);
+
+impl<T: DeserializeOwned + Send + 'static> FromContext for Query<T> {
+    fn from_context(ctx: &mut Context) -> BoxFuture<Self, Failure> {
+        let params = parse_query(ctx.req.uri.query().unwrap_or(""));
+        let result = T::deserialize(Params(params))
+            .map(Query)
+            .map_err(|err| {
+                ExtractError(format!("invalid query string: {}", err)).into_failure(StatusCode::BadRequest)
+            });
+        future::result(result).boxed()
+    }
+}
+
+pub(crate) fn parse_query(query: &str) -> Vec<(Option<String>, String)> {
+    let decode = |s: &str| {
+        let s = s.replace('+', " ");
+        percent_decode(s.as_bytes()).decode_utf8_lossy().into_owned()
+    };
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            let name = decode(kv.next().unwrap_or(""));
+            let value = decode(kv.next().unwrap_or(""));
+            (Some(name), value)
+        })
+        .collect()
+}
+
+
+/// The request body, deserialized from JSON.
+///
+/// The request is rejected with `415 Unsupported Media Type` if its content type is not JSON,
+/// `413 Payload Too Large` if the body exceeds `JSON_BODY_LIMIT`, and `400 Bad Request`
+/// if the body cannot be parsed.
+pub struct Json<T>(pub T);
+
+impl_deref!(Json);
+
+impl<T: DeserializeOwned + Send + 'static> FromContext for Json<T> {
+    fn from_context(ctx: &mut Context) -> BoxFuture<Self, Failure> {
+        let is_json = ctx.req.headers.get::<ContentType>().map_or(false, |ct| {
+            ct.type_() == mime::APPLICATION &&
+                (ct.subtype() == mime::JSON || ct.suffix() == Some(mime::JSON))
+        });
+        if !is_json {
+            let err = ExtractError("the content type must be application/json".to_owned());
+            return future::err(err.into_failure(StatusCode::UnsupportedMediaType)).boxed();
+        }
+        let body = match ctx.req.take_body() {
+            Some(body) => body,
+            None => {
+                let err = ExtractError("the request body has already been taken".to_owned());
+                return future::err(err.into_failure(StatusCode::InternalServerError)).boxed();
+            }
+        };
+
+        body.map_err(Failure::from)
+            .fold(Vec::new(), |mut buf, chunk| {
+                if buf.len() + chunk.len() > JSON_BODY_LIMIT {
+                    let err = ExtractError("the request body is too large".to_owned());
+                    return Err(err.into_failure(StatusCode::PayloadTooLarge));
+                }
+                buf.extend_from_slice(&chunk);
+                Ok(buf)
+            })
+            .and_then(|buf| {
+                serde_json::from_slice(&buf).map(Json).map_err(|err| {
+                    ExtractError(format!("invalid JSON body: {}", err)).into_failure(StatusCode::BadRequest)
+                })
+            })
+            .boxed()
+    }
+}
+
+
+/// A value stored in `ctx.ext` by previous middlewares.
+///
+/// The value is cloned out of the typemap. The request fails with
+/// `500 Internal Server Error` if the value has not been stored.
+pub struct Ext<K: Key>(pub K::Value);
+
+impl<K> FromContext for Ext<K>
+where
+    K: Key + 'static,
+    K::Value: Clone + Send,
+{
+    fn from_context(ctx: &mut Context) -> BoxFuture<Self, Failure> {
+        match ctx.ext.get::<K>() {
+            Some(value) => future::ok(Ext(value.clone())).boxed(),
+            None => {
+                let err = ExtractError(format!("no value in ctx.ext: {}", ::std::any::type_name::<K>()));
+                future::err(err.into_failure(StatusCode::InternalServerError)).boxed()
+            }
+        }
+    }
+}
+
+impl<K: Key> Deref for Ext<K> {
+    type Target = K::Value;
+    fn deref(&self) -> &K::Value {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_query;
+
+    #[test]
+    fn parse_query_pairs() {
+        let params = parse_query("q=hello+world&lang=ja%2Fjp&flag&");
+        assert_eq!(
+            params,
+            vec![
+                (Some("q".to_owned()), "hello world".to_owned()),
+                (Some("lang".to_owned()), "ja/jp".to_owned()),
+                (Some("flag".to_owned()), "".to_owned()),
+            ]
+        );
+    }
+}
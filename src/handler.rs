@@ -0,0 +1,104 @@
+//! Handlers which take extractors as their arguments.
+
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use futures::{future, Future, IntoFuture};
+use futures::future::BoxFuture;
+use hyper::Response;
+
+use context::Context;
+use extract::FromContext;
+use middleware::Middleware;
+use responder::Responder;
+use result::{AsyncResult, Failure};
+
+
+/// A function whose arguments are extracted from the request.
+///
+/// This trait is implemented for functions of up to five arguments implementing
+/// `FromContext`, whose return value is convertible into a future (e.g. `Result`
+/// or `BoxFuture`) of a `Responder`.
+pub trait Handler<Args>: 'static + Send + Sync {
+    fn call(&self, args: Args) -> BoxFuture<Response, Failure>;
+}
+
+macro_rules! impl_handler {
+    ($($T:ident),*) => {
+        impl<Func, R, $($T),*> Handler<($($T,)*)> for Func
+        where
+            Func: 'static + Send + Sync + Fn($($T),*) -> R,
+            R: IntoFuture,
+            R::Future: Send + 'static,
+            R::Item: Responder + 'static,
+            R::Error: Into<Failure> + 'static,
+        {
+            #[allow(non_snake_case)]
+            fn call(&self, args: ($($T,)*)) -> BoxFuture<Response, Failure> {
+                let ($($T,)*) = args;
+                (*self)($($T),*)
+                    .into_future()
+                    .map_err(Into::into)
+                    .and_then(Responder::into_response)
+                    .boxed()
+            }
+        }
+    }
+}
+
+impl_handler!();
+impl_handler!(A);
+impl_handler!(A, B);
+impl_handler!(A, B, C);
+impl_handler!(A, B, C, D);
+impl_handler!(A, B, C, D, E);
+
+
+/// Converts a handler into a `Middleware`.
+///
+/// ```ignore
+/// fn show_user(Path(id): Path<u32>, Query(q): Query<HashMap<String, String>>)
+///     -> Result<String, Failure>
+/// {
+///     Ok(format!("user {}", id))
+/// }
+///
+/// let router = Router::default().with_route(Get, r"/users/(\d+)", handler(show_user));
+/// ```
+pub fn handler<H, Args>(handler: H) -> HandlerMiddleware<H, Args>
+where
+    H: Handler<Args>,
+    Args: FromContext,
+{
+    HandlerMiddleware {
+        handler: Arc::new(handler),
+        _args: PhantomData,
+    }
+}
+
+
+/// A middleware which extracts the arguments of a handler and finishes the context
+/// with its response.
+///
+/// Created by `handler()`.
+pub struct HandlerMiddleware<H, Args> {
+    handler: Arc<H>,
+    _args: PhantomData<fn() -> Args>,
+}
+
+impl<H, Args> Middleware for HandlerMiddleware<H, Args>
+where
+    H: Handler<Args>,
+    Args: FromContext,
+{
+    fn call(&self, mut ctx: Context) -> AsyncResult {
+        let handler = self.handler.clone();
+        Args::from_context(&mut ctx)
+            .and_then(move |args| handler.call(args))
+            .then(move |result| match result {
+                Ok(res) => ctx.finish(res),
+                Err(failure) => future::err(failure).boxed(),
+            })
+            .boxed()
+    }
+}
@@ -5,38 +5,137 @@
 //! This project is not production ready.
 //!
 
+extern crate base64;
+extern crate bytes;
 #[doc(hidden)]
 pub extern crate futures;
+extern crate futures_cpupool;
 #[doc(hidden)]
 pub extern crate hyper;
+extern crate percent_encoding;
+#[cfg(feature = "pool")]
+#[doc(hidden)]
+pub extern crate r2d2;
+extern crate rand;
 extern crate regex;
+#[cfg(feature = "tls")]
+extern crate rustls;
+extern crate serde;
+extern crate serde_json;
+extern crate sha1;
+#[cfg(feature = "templates")]
+extern crate tera;
+extern crate tokio_codec;
 extern crate tokio_core;
+#[cfg_attr(feature = "tls", macro_use)]
+extern crate tokio_io;
 #[doc(hidden)]
 pub extern crate typemap;
+#[cfg(all(test, feature = "tls"))]
+extern crate webpki;
 
+pub mod access_log;
+mod blocking;
+pub mod cache;
+pub mod concurrency;
+pub mod conditional;
+pub mod connection;
 pub mod context;
+pub mod csrf;
+pub mod extract;
+pub mod handler;
+pub mod metrics;
 pub mod middleware;
+pub mod negotiate;
+#[cfg(feature = "pool")]
+pub mod pool;
+pub mod proxy;
+pub mod rate_limit;
 pub mod result;
 pub mod regex_pattern;
 pub mod request;
+pub mod request_id;
+pub mod responder;
 pub mod router;
+pub mod security_headers;
+pub mod server;
+pub mod sse;
+pub mod state;
+pub mod streaming;
 pub mod susanoo;
+#[cfg(feature = "templates")]
+pub mod templates;
+pub mod timeout;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod websocket;
 
 pub mod contrib {
     pub use futures;
     pub use hyper;
+    #[cfg(feature = "pool")]
+    pub use r2d2;
     pub use typemap;
 }
 
+#[doc(inline)]
+pub use access_log::AccessLog;
+#[doc(inline)]
+pub use cache::ResponseCache;
+#[doc(inline)]
+pub use concurrency::ConcurrencyLimit;
+#[doc(inline)]
+pub use conditional::Conditional;
+#[doc(inline)]
+pub use connection::Connection;
 #[doc(inline)]
 pub use context::Context;
 #[doc(inline)]
+pub use csrf::Csrf;
+#[doc(inline)]
+pub use extract::{FromContext, Path, Query, Json, Ext};
+#[doc(inline)]
+pub use handler::{handler, Handler};
+#[doc(inline)]
+pub use metrics::Metrics;
+#[doc(inline)]
 pub use middleware::{Middleware, Chain};
 #[doc(inline)]
-pub use result::{Failure, AsyncResult};
+pub use negotiate::Negotiate;
+#[cfg(feature = "pool")]
+#[doc(inline)]
+pub use pool::{ConnectionPool, Conn, Transaction, Transactional};
+#[doc(inline)]
+pub use responder::{Responder, Html, Redirect};
+#[doc(inline)]
+pub use result::{Failure, AsyncResult, ErrorHandler};
+#[doc(inline)]
+pub use server::Server;
+#[doc(inline)]
+pub use sse::{Sse, Event};
+#[doc(inline)]
+pub use state::State;
 #[doc(inline)]
 pub use susanoo::Susanoo;
+#[cfg(feature = "templates")]
+#[doc(inline)]
+pub use templates::{Renderer, Templates, TeraRenderer};
+#[doc(inline)]
+pub use proxy::TrustedProxies;
 #[doc(inline)]
-pub use router::Router;
+pub use rate_limit::RateLimit;
+#[doc(inline)]
+pub use request_id::RequestIdMiddleware;
+#[doc(inline)]
+pub use router::{Router, TrailingSlash};
+#[doc(inline)]
+pub use security_headers::{SecurityHeaders, ContentSecurityPolicy};
 #[doc(inline)]
 pub use regex_pattern::OwnedCaptures;
+#[doc(inline)]
+pub use timeout::Timeout;
+#[cfg(feature = "tls")]
+#[doc(inline)]
+pub use tls::TlsConfig;
+#[doc(inline)]
+pub use websocket::{WebSocket, Message};
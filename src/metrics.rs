@@ -0,0 +1,302 @@
+//! Request metrics, exposed in the Prometheus text format.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::Future;
+use hyper::{Method, Response, StatusCode};
+use hyper::header::ContentType;
+use typemap::SendMap;
+
+use context::Context;
+use middleware::Middleware;
+use result::AsyncResult;
+use router::MatchedRoute;
+
+
+const DEFAULT_BUCKETS: &[f64] = &[
+    0.005,
+    0.01,
+    0.025,
+    0.05,
+    0.1,
+    0.25,
+    0.5,
+    1.0,
+    2.5,
+    5.0,
+    10.0,
+];
+
+
+/// A registry of request counters and latency histograms.
+///
+/// The series are labelled by the request method, the pattern of the route
+/// matched by `Router` (not the raw path, in order to keep the cardinality low),
+/// and the class of the response status (e.g. `2xx`).
+///
+/// The registry is cheaply cloneable, and all clones share the same series.
+#[derive(Clone)]
+pub struct Metrics {
+    inner: Arc<Mutex<Registry>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::with_buckets(DEFAULT_BUCKETS.to_vec())
+    }
+}
+
+impl Metrics {
+    /// Creates a registry whose histograms use the given upper bounds of buckets, in seconds.
+    ///
+    /// The bounds are sorted, and duplicates are removed. NaN and infinities are ignored,
+    /// since the `+Inf` bucket is always rendered.
+    pub fn with_buckets(mut buckets: Vec<f64>) -> Self {
+        buckets.retain(|le| le.is_finite());
+        buckets.sort_by(f64::total_cmp);
+        buckets.dedup();
+        Metrics {
+            inner: Arc::new(Mutex::new(Registry {
+                buckets,
+                series: BTreeMap::new(),
+            })),
+        }
+    }
+
+    /// Wraps a middleware so that the requests handled by it are recorded to this registry.
+    pub fn middleware<M: Middleware>(&self, inner: M) -> MetricsMiddleware<M> {
+        MetricsMiddleware {
+            inner,
+            metrics: self.clone(),
+        }
+    }
+
+    /// Returns a handler which serves the metrics in the text exposition format.
+    ///
+    /// The handler is typically mounted on a `Router`, e.g. at `/metrics`.
+    pub fn handler(&self) -> MetricsHandler {
+        MetricsHandler { metrics: self.clone() }
+    }
+
+    /// Records a request.
+    pub fn observe(&self, method: &Method, route: &str, status: StatusCode, latency: Duration) {
+        let labels = Labels {
+            method: method.to_string(),
+            route: route.to_owned(),
+            status: status_class(status),
+        };
+        let latency = latency.as_secs() as f64 + f64::from(latency.subsec_nanos()) / 1e9;
+
+        let mut registry = match self.inner.lock() {
+            Ok(registry) => registry,
+            Err(_) => return,
+        };
+        let num_buckets = registry.buckets.len();
+        let bucket = registry.buckets.iter().position(|&le| latency <= le);
+        let series = registry.series.entry(labels).or_insert_with(|| {
+            Series {
+                count: 0,
+                sum: 0.0,
+                buckets: vec![0; num_buckets],
+            }
+        });
+        series.count += 1;
+        series.sum += latency;
+        if let Some(i) = bucket {
+            series.buckets[i] += 1;
+        }
+    }
+
+    /// Renders the metrics in the text exposition format.
+    pub fn render(&self) -> String {
+        let registry = match self.inner.lock() {
+            Ok(registry) => registry,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let mut out = String::new();
+
+        out.push_str("# HELP http_requests_total The total number of HTTP requests.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for (labels, series) in &registry.series {
+            let _ = writeln!(out, "http_requests_total{{{}}} {}", labels, series.count);
+        }
+
+        out.push_str("# HELP http_request_duration_seconds The latencies of HTTP requests in seconds.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for (labels, series) in &registry.series {
+            let mut cumulative = 0;
+            for (le, count) in registry.buckets.iter().zip(&series.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels,
+                    le,
+                    cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels,
+                series.count
+            );
+            let _ = writeln!(out, "http_request_duration_seconds_sum{{{}}} {}", labels, series.sum);
+            let _ = writeln!(out, "http_request_duration_seconds_count{{{}}} {}", labels, series.count);
+        }
+
+        out
+    }
+}
+
+
+struct Registry {
+    buckets: Vec<f64>,
+    series: BTreeMap<Labels, Series>,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Labels {
+    method: String,
+    route: String,
+    status: &'static str,
+}
+
+impl fmt::Display for Labels {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "method=\"{}\",route=\"{}\",status=\"{}\"",
+            escape(&self.method),
+            escape(&self.route),
+            self.status
+        )
+    }
+}
+
+struct Series {
+    count: u64,
+    sum: f64,
+    buckets: Vec<u64>,
+}
+
+fn status_class(status: StatusCode) -> &'static str {
+    match status.as_u16() / 100 {
+        1 => "1xx",
+        2 => "2xx",
+        3 => "3xx",
+        4 => "4xx",
+        _ => "5xx",
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+
+/// A middleware which records the requests handled by the inner middleware.
+///
+/// Created by `Metrics::middleware()`.
+pub struct MetricsMiddleware<M> {
+    inner: M,
+    metrics: Metrics,
+}
+
+impl<M: Middleware> MetricsMiddleware<M> {
+    fn observe<F>(&self, ctx: Context, f: F) -> AsyncResult
+    where
+        F: FnOnce(&M, Context) -> AsyncResult,
+    {
+        let start = Instant::now();
+        let method = ctx.req.method.clone();
+        let metrics = self.metrics.clone();
+
+        f(&self.inner, ctx)
+            .then(move |result| {
+                {
+                    let (status, ext) = match result {
+                        Ok(ref ctx) => {
+                            let status = ctx.res.as_ref().map_or(StatusCode::NotFound, |res| res.status());
+                            (status, &ctx.ext)
+                        }
+                        Err(ref failure) => (failure.response.status(), &failure.ext),
+                    };
+                    metrics.observe(&method, matched_route(ext), status, start.elapsed());
+                }
+                result
+            })
+            .boxed()
+    }
+}
+
+impl<M: Middleware> Middleware for MetricsMiddleware<M> {
+    fn call(&self, ctx: Context) -> AsyncResult {
+        self.observe(ctx, |inner, ctx| inner.call(ctx))
+    }
+
+    fn after(&self, ctx: Context) -> AsyncResult {
+        self.observe(ctx, |inner, ctx| inner.after(ctx))
+    }
+}
+
+fn matched_route(ext: &SendMap) -> &str {
+    ext.get::<MatchedRoute>().map_or("unmatched", |route| route.0.as_str())
+}
+
+
+/// A handler which serves the metrics in the text exposition format.
+///
+/// Created by `Metrics::handler()`.
+pub struct MetricsHandler {
+    metrics: Metrics,
+}
+
+impl Middleware for MetricsHandler {
+    fn call(&self, ctx: Context) -> AsyncResult {
+        let res = Response::new()
+            .with_status(StatusCode::Ok)
+            .with_header(ContentType("text/plain; version=0.0.4".parse().unwrap()))
+            .with_body(self.metrics.render());
+        ctx.finish(res)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use hyper::{Get, StatusCode};
+    use super::Metrics;
+
+    #[test]
+    fn render_series() {
+        let metrics = Metrics::with_buckets(vec![0.1, 1.0]);
+        metrics.observe(&Get, "/users/(\\d+)", StatusCode::Ok, Duration::from_millis(50));
+        metrics.observe(&Get, "/users/(\\d+)", StatusCode::Ok, Duration::from_millis(500));
+
+        let rendered = metrics.render();
+        let labels = r#"method="GET",route="/users/(\\d+)",status="2xx""#;
+        assert!(rendered.contains(&format!("http_requests_total{{{}}} 2\n", labels)));
+        assert!(rendered.contains(&format!(
+            "http_request_duration_seconds_bucket{{{},le=\"0.1\"}} 1\n",
+            labels
+        )));
+        assert!(rendered.contains(&format!(
+            "http_request_duration_seconds_bucket{{{},le=\"1\"}} 2\n",
+            labels
+        )));
+    }
+
+    #[test]
+    fn sanitize_buckets() {
+        let metrics = Metrics::with_buckets(vec![1.0, f64::NAN, 0.1, f64::INFINITY, 0.1]);
+        assert_eq!(metrics.inner.lock().unwrap().buckets, vec![0.1, 1.0]);
+    }
+}
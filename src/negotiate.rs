@@ -0,0 +1,203 @@
+//! Content negotiation based on `Accept`.
+
+use std::error::Error as StdError;
+use std::fmt;
+
+use futures::{future, Future};
+use hyper::{Response, StatusCode};
+use hyper::header::{Accept, ContentType, Quality, QualityItem, q};
+use hyper::mime::{self, Mime};
+use serde::Serialize;
+
+use context::Context;
+use extract::Json;
+use responder::{Html, Responder};
+use result::{AsyncResult, Failure};
+
+
+/// The error returned when none of the representations is acceptable to the client.
+#[derive(Debug)]
+pub struct NotAcceptable;
+
+impl fmt::Display for NotAcceptable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "none of the representations is acceptable")
+    }
+}
+
+impl StdError for NotAcceptable {
+    fn description(&self) -> &str {
+        "not acceptable"
+    }
+}
+
+
+/// A set of renderers of the same resource in different media types.
+///
+/// The renderers are listed in the order of the server's preference, which breaks ties
+/// between the media types the client accepts equally. Only the chosen renderer is called.
+#[derive(Default)]
+pub struct Negotiate {
+    renderers: Vec<(Mime, Box<FnOnce() -> Result<Response, Failure>>)>,
+}
+
+impl Negotiate {
+    /// Creates an empty set of renderers.
+    pub fn new() -> Self {
+        Negotiate::default()
+    }
+
+    /// Adds a renderer of the media type.
+    ///
+    /// `Content-Type` of the rendered response is set to the media type,
+    /// unless it is already set to the same type (e.g. with another charset).
+    pub fn with<F, R>(mut self, media_type: Mime, render: F) -> Self
+    where
+        F: FnOnce() -> R + 'static,
+        R: Responder,
+    {
+        self.renderers
+            .push((media_type, Box::new(move || render().into_response())));
+        self
+    }
+
+    /// Adds a renderer of `application/json`, which serializes the returned value.
+    pub fn json<F, T>(self, render: F) -> Self
+    where
+        F: FnOnce() -> T + 'static,
+        T: Serialize,
+    {
+        self.with(mime::APPLICATION_JSON, move || Json(render()))
+    }
+
+    /// Adds a renderer of `text/html`, e.g. a template.
+    pub fn html<F, R>(self, render: F) -> Self
+    where
+        F: FnOnce() -> R + 'static,
+        R: Into<String>,
+    {
+        self.with(mime::TEXT_HTML_UTF_8, move || Html(render()))
+    }
+
+    /// Adds a renderer of `text/plain`.
+    pub fn text<F, R>(self, render: F) -> Self
+    where
+        F: FnOnce() -> R + 'static,
+        R: Into<String>,
+    {
+        self.with(mime::TEXT_PLAIN_UTF_8, move || render().into())
+    }
+}
+
+
+impl Context {
+    /// Finishes the context with the representation preferred by `Accept` of the request.
+    ///
+    /// The request fails with `406 Not Acceptable` if the client accepts none of them.
+    /// The response always has `Vary: Accept`, since it depends on the header.
+    pub fn negotiate(self, negotiate: Negotiate) -> AsyncResult {
+        let mut renderers = negotiate.renderers;
+        let chosen = {
+            let available: Vec<&Mime> = renderers.iter().map(|&(ref mime, _)| mime).collect();
+            preferred(self.req.headers.get::<Accept>(), &available)
+        };
+        let (media_type, render) = match chosen {
+            Some(i) => renderers.swap_remove(i),
+            None => {
+                let mut res = Response::new()
+                    .with_status(StatusCode::NotAcceptable)
+                    .with_body("Not Acceptable");
+                res.headers_mut().append_raw("Vary", "Accept");
+                return future::err(Failure::from(NotAcceptable).with_response(res)).boxed();
+            }
+        };
+
+        let mut res = match render() {
+            Ok(res) => res,
+            Err(failure) => return future::err(failure).boxed(),
+        };
+        let same_type = res.headers()
+            .get::<ContentType>()
+            .map_or(false, |&ContentType(ref m)| {
+                m.type_() == media_type.type_() && m.subtype() == media_type.subtype()
+            });
+        if !same_type {
+            res.headers_mut().set(ContentType(media_type));
+        }
+        res.headers_mut().append_raw("Vary", "Accept");
+        self.finish(res)
+    }
+}
+
+
+/// Returns the index of the media type most preferred by the `Accept` header.
+///
+/// Each media type is weighted by the most specific range matching it. Ties are broken
+/// by the order of `available`. All types are acceptable if the header is missing.
+pub fn preferred(accept: Option<&Accept>, available: &[&Mime]) -> Option<usize> {
+    let accept = match accept {
+        Some(accept) if !accept.is_empty() => accept,
+        _ => return if available.is_empty() { None } else { Some(0) },
+    };
+
+    let mut best: Option<(usize, Quality)> = None;
+    for (i, mime) in available.iter().enumerate() {
+        let quality = match quality_of(accept, mime) {
+            Some(quality) if quality > q(0) => quality,
+            _ => continue,
+        };
+        if best.map_or(true, |(_, best)| quality > best) {
+            best = Some((i, quality));
+        }
+    }
+    best.map(|(i, _)| i)
+}
+
+fn quality_of(accept: &[QualityItem<Mime>], mime: &Mime) -> Option<Quality> {
+    accept
+        .iter()
+        .filter_map(|range| {
+            let item = &range.item;
+            let specificity = if item.type_() == mime::STAR {
+                0
+            } else if item.type_() != mime.type_() {
+                return None;
+            } else if item.subtype() == mime::STAR {
+                1
+            } else if item.subtype() != mime.subtype() {
+                return None;
+            } else {
+                2
+            };
+            Some((specificity, range.quality))
+        })
+        .max_by_key(|&(specificity, _)| specificity)
+        .map(|(_, quality)| quality)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefer_by_quality_and_order() {
+        let available = [&mime::APPLICATION_JSON, &mime::TEXT_HTML_UTF_8, &mime::TEXT_PLAIN_UTF_8];
+        let accept = |s: &str| {
+            use hyper::header::Header;
+            Accept::parse_header(&s.into()).unwrap()
+        };
+
+        assert_eq!(preferred(None, &available), Some(0));
+        assert_eq!(
+            preferred(Some(&accept("text/html,application/xhtml+xml,*/*;q=0.8")), &available),
+            Some(1)
+        );
+        assert_eq!(preferred(Some(&accept("text/*;q=0.5, */*;q=0.1")), &available), Some(1));
+        assert_eq!(
+            preferred(Some(&accept("text/*, text/html;q=0")), &available),
+            Some(2)
+        );
+        assert_eq!(preferred(Some(&accept("image/png")), &available), None);
+    }
+}
@@ -0,0 +1,531 @@
+//! Pools of database connections, based on r2d2 (requires the feature `pool`).
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use futures::{future, Future};
+use futures::future::BoxFuture;
+use hyper::{Response, StatusCode};
+use r2d2::{ManageConnection, Pool, PooledConnection};
+use typemap::Key;
+
+use blocking::{self, BlockingPool};
+use concurrency::{Permit, Semaphore};
+use context::Context;
+use extract::FromContext;
+use middleware::Middleware;
+use result::{AsyncResult, Failure};
+
+
+/// The error returned when a connection is requested without `ConnectionPool`.
+#[derive(Debug)]
+pub struct MissingPool;
+
+impl fmt::Display for MissingPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no connection pool; wrap the middleware with ConnectionPool")
+    }
+}
+
+impl StdError for MissingPool {
+    fn description(&self) -> &str {
+        "missing connection pool"
+    }
+}
+
+
+/// The error returned when no connection of the pool becomes free within its `connection_timeout`.
+#[derive(Debug)]
+pub struct PoolExhausted;
+
+impl fmt::Display for PoolExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "timed out waiting for a connection of the pool")
+    }
+}
+
+impl StdError for PoolExhausted {
+    fn description(&self) -> &str {
+        "connection pool exhausted"
+    }
+}
+
+
+/// A middleware which makes the connection pool available to the inner middleware,
+/// through the `Conn` extractor.
+///
+/// ```ignore
+/// let pool = r2d2::Pool::builder()
+///     .max_size(10)
+///     .connection_timeout(Duration::from_secs(5))
+///     .build(manager)?;
+/// let router = Router::default().with_route(Get, "/", handler(index));
+/// let app = ConnectionPool::new(router, pool);
+/// ```
+///
+/// The connections checked out through the middleware never exceed `max_size` of the pool,
+/// so the pool should not be shared with other middlewares or threads. Since a query waiting
+/// for a lock held by another connection occupies its thread, `Susanoo::with_blocking_threads()`
+/// should be at least `max_size`, so that the holder of the lock can always proceed.
+pub struct ConnectionPool<M, C: ManageConnection> {
+    inner: M,
+    pool: SharedPool<C>,
+}
+
+impl<M: Middleware, C: ManageConnection> ConnectionPool<M, C> {
+    /// Creates a middleware which provides the connections of the pool.
+    pub fn new(inner: M, pool: Pool<C>) -> Self {
+        let semaphore = Arc::new(Semaphore::new(pool.max_size() as usize));
+        ConnectionPool {
+            inner,
+            pool: SharedPool { pool, semaphore },
+        }
+    }
+}
+
+impl<M: Middleware, C: ManageConnection> Middleware for ConnectionPool<M, C> {
+    fn call(&self, mut ctx: Context) -> AsyncResult {
+        ctx.ext.insert::<PoolKey<C>>(self.pool.clone());
+        self.inner.call(ctx)
+    }
+
+    fn after(&self, ctx: Context) -> AsyncResult {
+        self.inner.after(ctx)
+    }
+}
+
+
+struct PoolKey<C>(PhantomData<C>);
+
+impl<C: ManageConnection> Key for PoolKey<C> {
+    type Value = SharedPool<C>;
+}
+
+/// The pool, and the permits of checking out its connections.
+struct SharedPool<C: ManageConnection> {
+    pool: Pool<C>,
+    semaphore: Arc<Semaphore>,
+}
+
+impl<C: ManageConnection> Clone for SharedPool<C> {
+    fn clone(&self) -> Self {
+        SharedPool {
+            pool: self.pool.clone(),
+            semaphore: self.semaphore.clone(),
+        }
+    }
+}
+
+
+/// A connection checked out from the pool of `ConnectionPool`, used as an argument of handlers.
+///
+/// Requests wait for a free connection on the event loop, and the connection is checked out
+/// on the blocking thread pool. If none becomes free within `connection_timeout` of the pool,
+/// the request fails with `503 Service Unavailable`.
+///
+/// If a connection is already stored in `ctx.ext` (e.g. by `Transaction`), it is shared
+/// instead. The connection returns to the pool when all of its clones are dropped.
+pub struct Conn<C: ManageConnection> {
+    checkout: Arc<Checkout<C>>,
+    blocking: Option<Arc<BlockingPool>>,
+}
+
+struct Checkout<C: ManageConnection> {
+    conn: Mutex<PooledConnection<C>>,
+    // Dropped after the connection has returned to the pool.
+    _permit: Permit,
+}
+
+impl<C: ManageConnection> Clone for Conn<C> {
+    fn clone(&self) -> Self {
+        Conn {
+            checkout: self.checkout.clone(),
+            blocking: self.blocking.clone(),
+        }
+    }
+}
+
+impl<C: ManageConnection> Key for Conn<C> {
+    type Value = Self;
+}
+
+impl<C: ManageConnection> Conn<C> {
+    /// Runs a blocking function with the connection (e.g. a query) on the blocking thread pool,
+    /// and returns a future of its result.
+    pub fn run<F, T, E>(&self, f: F) -> BoxFuture<T, Failure>
+    where
+        F: FnOnce(&mut C::Connection) -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: Into<Failure> + Send + 'static,
+    {
+        let checkout = self.checkout.clone();
+        blocking::spawn(self.blocking.as_ref().map(|pool| &**pool), move || {
+            let mut conn = lock(&checkout.conn);
+            f(&mut **conn)
+        })
+    }
+
+    /// Locks the connection for use in place.
+    ///
+    /// Queries block the current thread, so call it only off the event loop
+    /// (e.g. in `Context::spawn_blocking()`).
+    pub fn lock(&self) -> MutexGuard<PooledConnection<C>> {
+        lock(&self.checkout.conn)
+    }
+
+    /// Checks out a connection from the pool stored by `ConnectionPool`.
+    pub(crate) fn checkout(ctx: &Context) -> BoxFuture<Self, Failure> {
+        let pool = match ctx.ext.get::<PoolKey<C>>() {
+            Some(pool) => pool.clone(),
+            None => {
+                return future::err(Failure::from(MissingPool).with_response(
+                    Response::new()
+                        .with_status(StatusCode::InternalServerError)
+                        .with_body("Internal Server Error"),
+                )).boxed();
+            }
+        };
+        let blocking = ctx.blocking_pool();
+        let timeout = pool.pool.connection_timeout();
+        let permit = pool.semaphore
+            .acquire(ctx.handle(), usize::max_value(), Some(timeout), exhausted);
+        permit
+            .and_then(move |permit| {
+                let checkout = blocking::spawn(blocking.as_ref().map(|pool| &**pool), move || {
+                    pool.pool.get().map_err(unavailable)
+                });
+                checkout.map(move |conn| Conn {
+                    checkout: Arc::new(Checkout {
+                        conn: Mutex::new(conn),
+                        _permit: permit,
+                    }),
+                    blocking,
+                })
+            })
+            .boxed()
+    }
+}
+
+impl<C: ManageConnection> FromContext for Conn<C> {
+    fn from_context(ctx: &mut Context) -> BoxFuture<Self, Failure> {
+        match ctx.ext.get::<Conn<C>>() {
+            Some(conn) => future::ok(conn.clone()).boxed(),
+            None => Conn::checkout(ctx),
+        }
+    }
+}
+
+
+/// The statements which begin and finish transactions on the connections of a pool.
+///
+/// ```ignore
+/// struct Sqlite;
+///
+/// impl Transactional for Sqlite {
+///     type Manager = SqliteConnectionManager;
+///     fn begin(&self, conn: &mut SqliteConnection) -> Result<(), Failure> {
+///         Ok(conn.execute_batch("BEGIN")?)
+///     }
+///     // ...
+/// }
+/// ```
+pub trait Transactional: Send + Sync + 'static {
+    type Manager: ManageConnection;
+
+    fn begin(&self, conn: &mut <Self::Manager as ManageConnection>::Connection) -> Result<(), Failure>;
+
+    fn commit(&self, conn: &mut <Self::Manager as ManageConnection>::Connection) -> Result<(), Failure>;
+
+    fn rollback(&self, conn: &mut <Self::Manager as ManageConnection>::Connection) -> Result<(), Failure>;
+}
+
+
+/// A middleware which runs the inner middleware in a transaction.
+///
+/// A connection is checked out from the pool of `ConnectionPool` and a transaction is begun
+/// on it, before the inner middleware is called. The connection is stored in `ctx.ext`, so that
+/// the `Conn` extractor of the handler shares it. When the future of the inner middleware
+/// resolves, the transaction is committed unless the response is `4xx` or `5xx`, the context
+/// has no response, or a `Failure` is returned, in which case it is rolled back.
+///
+/// The transaction is finished there rather than in `after()`, since `after()` is only called
+/// when the response has already been set before this middleware, so the inner middleware
+/// does not run in the transaction.
+///
+/// If the request is dropped before the transaction is finished (e.g. on timeout, or when
+/// the client disconnects), it is rolled back on the blocking thread pool before the connection
+/// returns to the pool.
+///
+/// The request fails with `500 Internal Server Error` if the transaction cannot be finished.
+/// Transactions must not be nested.
+pub struct Transaction<M, T> {
+    inner: Arc<M>,
+    transactional: Arc<T>,
+}
+
+impl<M: Middleware, T: Transactional> Transaction<M, T> {
+    /// Creates a middleware which begins and finishes transactions by `transactional`.
+    pub fn new(inner: M, transactional: T) -> Self {
+        Transaction {
+            inner: Arc::new(inner),
+            transactional: Arc::new(transactional),
+        }
+    }
+}
+
+impl<M: Middleware, T: Transactional> Middleware for Transaction<M, T> {
+    fn call(&self, mut ctx: Context) -> AsyncResult {
+        let inner = self.inner.clone();
+        let transactional = self.transactional.clone();
+        Conn::<T::Manager>::from_context(&mut ctx)
+            .and_then(move |conn| {
+                // Armed before `begin`, which may complete even if the request is dropped.
+                let open = OpenTransaction {
+                    conn: Some(conn.clone()),
+                    transactional: transactional.clone(),
+                };
+                let begun = {
+                    let transactional = transactional.clone();
+                    conn.run(move |c| transactional.begin(c))
+                };
+                begun.map(move |()| (ctx, conn, transactional, open))
+            })
+            .and_then(move |(mut ctx, conn, transactional, open)| {
+                ctx.ext.insert::<Conn<T::Manager>>(conn.clone());
+                inner.call(ctx).then(move |result| {
+                    let commit = should_commit(&result);
+                    let finished = conn.run(move |c| {
+                        if commit {
+                            transactional.commit(c)
+                        } else {
+                            transactional.rollback(c)
+                        }
+                    });
+                    finished.then(move |finished| {
+                        open.close();
+                        match (result, finished) {
+                            (Ok(mut ctx), Ok(())) => {
+                                // Return the connection to the pool before the response is sent.
+                                ctx.ext.remove::<Conn<T::Manager>>();
+                                Ok(ctx)
+                            }
+                            (Ok(_), Err(failure)) | (Err(failure), _) => Err(failure),
+                        }
+                    })
+                })
+            })
+            .boxed()
+    }
+
+    fn after(&self, ctx: Context) -> AsyncResult {
+        self.inner.after(ctx)
+    }
+}
+
+
+/// A transaction begun on the connection, which is rolled back when dropped
+/// unless the connection is taken out after the transaction is finished.
+struct OpenTransaction<T: Transactional> {
+    conn: Option<Conn<T::Manager>>,
+    transactional: Arc<T>,
+}
+
+impl<T: Transactional> OpenTransaction<T> {
+    /// Disarms the rollback, after the transaction has been finished.
+    fn close(mut self) {
+        self.conn.take();
+    }
+}
+
+impl<T: Transactional> Drop for OpenTransaction<T> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            let transactional = self.transactional.clone();
+            let checkout = conn.checkout.clone();
+            // The connection returns to the pool when the rollback has finished.
+            blocking::spawn_detached(conn.blocking.as_ref().map(|pool| &**pool), move || {
+                let _ = transactional.rollback(&mut **lock(&checkout.conn));
+            });
+        }
+    }
+}
+
+/// Returns whether the transaction is committed after the inner middleware returns the result.
+fn should_commit(result: &Result<Context, Failure>) -> bool {
+    match *result {
+        Ok(ref ctx) => ctx.res.as_ref().map_or(false, |res| {
+            !res.status().is_client_error() && !res.status().is_server_error()
+        }),
+        Err(_) => false,
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+fn exhausted() -> Failure {
+    unavailable(PoolExhausted)
+}
+
+fn unavailable<E: StdError + Send + 'static>(err: E) -> Failure {
+    Failure::from(err).with_response(
+        Response::new()
+            .with_status(StatusCode::ServiceUnavailable)
+            .with_body("Service Unavailable"),
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    use hyper::{Get, Request};
+    use tokio_core::reactor::{Core, Timeout};
+
+    use connection::Connection;
+    use super::*;
+
+    /// Connections numbered in order of creation, which record the statements run on them.
+    struct Memory(AtomicUsize);
+
+    struct MemoryConnection {
+        id: usize,
+        statements: Vec<&'static str>,
+    }
+
+    impl ManageConnection for Memory {
+        type Connection = MemoryConnection;
+        type Error = io::Error;
+
+        fn connect(&self) -> Result<MemoryConnection, io::Error> {
+            Ok(MemoryConnection {
+                id: self.0.fetch_add(1, Ordering::SeqCst),
+                statements: Vec::new(),
+            })
+        }
+
+        fn is_valid(&self, _: &mut MemoryConnection) -> Result<(), io::Error> {
+            Ok(())
+        }
+
+        fn has_broken(&self, _: &mut MemoryConnection) -> bool {
+            false
+        }
+    }
+
+    struct Statements;
+
+    impl Transactional for Statements {
+        type Manager = Memory;
+
+        fn begin(&self, conn: &mut MemoryConnection) -> Result<(), Failure> {
+            conn.statements.push("BEGIN");
+            Ok(())
+        }
+
+        fn commit(&self, conn: &mut MemoryConnection) -> Result<(), Failure> {
+            conn.statements.push("COMMIT");
+            Ok(())
+        }
+
+        fn rollback(&self, conn: &mut MemoryConnection) -> Result<(), Failure> {
+            conn.statements.push("ROLLBACK");
+            Ok(())
+        }
+    }
+
+    /// Creates a context on the event loop, with a pool of a single connection.
+    fn context(core: &Core) -> Context {
+        let pool = Pool::builder()
+            .max_size(1)
+            .connection_timeout(Duration::from_millis(50))
+            .build(Memory(AtomicUsize::new(0)))
+            .unwrap();
+        let conn = Connection::default().with_remote(core.remote());
+        let mut ctx = Context::new(Request::new(Get, "/".parse().unwrap()), Arc::new(conn));
+        ctx.ext.insert::<PoolKey<Memory>>(SharedPool {
+            pool,
+            semaphore: Arc::new(Semaphore::new(1)),
+        });
+        ctx
+    }
+
+    #[test]
+    fn wait_for_free_connection() {
+        let mut core = Core::new().unwrap();
+        let ctx = context(&core);
+        // Checked out on the event loop, so that the timeout is driven by it.
+        let checkout = |core: &mut Core| core.run(future::lazy(|| Conn::<Memory>::checkout(&ctx)));
+
+        let first = checkout(&mut core).ok().unwrap();
+        assert_eq!(first.lock().id, 0);
+
+        // The second checkout waits for the permit, and times out.
+        let start = Instant::now();
+        let failure = checkout(&mut core).err().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(failure.err.downcast_ref::<PoolExhausted>().is_some());
+        assert_eq!(failure.response.status(), StatusCode::ServiceUnavailable);
+
+        // Released while the third checkout waits, the connection returns to the pool
+        // before the permit is handed to the waiter.
+        let release = Timeout::new(Duration::from_millis(10), &core.handle())
+            .unwrap()
+            .then(move |_| {
+                drop(first);
+                Ok(())
+            });
+        core.handle().spawn(release);
+        let third = checkout(&mut core).ok().unwrap();
+        assert_eq!(third.lock().id, 0);
+    }
+
+    #[test]
+    fn rollback_dropped_request() {
+        fn pending(_: Context) -> AsyncResult {
+            future::empty().boxed()
+        }
+
+        let mut core = Core::new().unwrap();
+        let ctx = context(&core);
+        let pool = ctx.ext.get::<PoolKey<Memory>>().unwrap().clone();
+        let transaction = Transaction::new(pending, Statements);
+
+        // The request is dropped while the handler is running (e.g. by a timeout).
+        let timeout = Timeout::new(Duration::from_millis(10), &core.handle()).unwrap();
+        let request = future::lazy(|| transaction.call(ctx));
+        let _ = core.run(request.select2(timeout));
+
+        let conn = pool.pool.get().unwrap();
+        assert_eq!(conn.id, 0);
+        assert_eq!(conn.statements, vec!["BEGIN", "ROLLBACK"]);
+    }
+
+    #[test]
+    fn commit_unless_error() {
+        let response = |status| {
+            let mut ctx = Context::from_hyper(Request::new(Get, "/".parse().unwrap()));
+            ctx.res = Some(Response::new().with_status(status));
+            Ok(ctx)
+        };
+        assert!(should_commit(&response(StatusCode::Ok)));
+        assert!(should_commit(&response(StatusCode::Created)));
+        assert!(should_commit(&response(StatusCode::SeeOther)));
+        assert!(!should_commit(&response(StatusCode::BadRequest)));
+        assert!(!should_commit(&response(StatusCode::Conflict)));
+        assert!(!should_commit(&response(StatusCode::InternalServerError)));
+
+        let no_response = Context::from_hyper(Request::new(Get, "/".parse().unwrap()));
+        assert!(!should_commit(&Ok(no_response)));
+        assert!(!should_commit(&Err(Failure::from(PoolExhausted))));
+    }
+}
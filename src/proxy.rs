@@ -0,0 +1,206 @@
+//! Resolution of the client address behind trusted reverse proxies.
+
+use std::net::{IpAddr, SocketAddr};
+use hyper::header::Headers;
+use typemap::Key;
+
+use context::Context;
+use middleware::Middleware;
+use result::AsyncResult;
+
+
+/// The IP address of the original client, resolved by `TrustedProxies`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientAddr(pub IpAddr);
+
+impl Key for ClientAddr {
+    type Value = Self;
+}
+
+
+/// A middleware which resolves the real client address from `Forwarded` or
+/// `X-Forwarded-For` headers.
+///
+/// The headers are trusted only if the peer of the connection is one of the
+/// registered proxies. The entries in the headers are walked from right to left,
+/// and the first address which is not a trusted proxy is regarded as the client.
+/// The result is stored in `ctx.ext` as `ClientAddr`, and can be obtained by
+/// `Context::client_ip()`.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: Vec<(IpAddr, u8)>,
+}
+
+impl TrustedProxies {
+    /// Trust a proxy with given address.
+    pub fn with_proxy(self, addr: IpAddr) -> Self {
+        let prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+        self.with_network(addr, prefix_len)
+    }
+
+    /// Trust all proxies in the network specified by an address and its prefix length
+    /// (e.g. `10.0.0.0/8`).
+    pub fn with_network(mut self, addr: IpAddr, prefix_len: u8) -> Self {
+        self.networks.push((addr, prefix_len));
+        self
+    }
+
+    /// Returns whether the address is one of the trusted proxies.
+    pub fn is_trusted(&self, addr: &IpAddr) -> bool {
+        self.networks.iter().any(|&(ref network, prefix_len)| {
+            in_network(addr, network, prefix_len)
+        })
+    }
+
+    fn resolve(&self, peer: IpAddr, headers: &Headers) -> IpAddr {
+        if !self.is_trusted(&peer) {
+            return peer;
+        }
+        let mut client = peer;
+        for addr in forwarded_addrs(headers).into_iter().rev() {
+            match addr {
+                Some(addr) => {
+                    client = addr;
+                    if !self.is_trusted(&addr) {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        client
+    }
+}
+
+impl Middleware for TrustedProxies {
+    fn call(&self, mut ctx: Context) -> AsyncResult {
+        if let Some(peer) = ctx.conn.peer_addr() {
+            let client = self.resolve(peer.ip(), &ctx.req.headers);
+            ctx.ext.insert::<ClientAddr>(ClientAddr(client));
+        }
+        ctx.next()
+    }
+}
+
+
+impl Context {
+    /// Returns the IP address of the client.
+    ///
+    /// If the address has been resolved by `TrustedProxies`, it is returned.
+    /// Otherwise, the address of the peer is returned.
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        match self.ext.get::<ClientAddr>() {
+            Some(&ClientAddr(addr)) => Some(addr),
+            None => self.conn.peer_addr().map(|addr| addr.ip()),
+        }
+    }
+}
+
+
+fn in_network(addr: &IpAddr, network: &IpAddr, prefix_len: u8) -> bool {
+    let (addr, network) = match (*addr, *network) {
+        (IpAddr::V4(addr), IpAddr::V4(network)) => (addr.octets().to_vec(), network.octets().to_vec()),
+        (IpAddr::V6(addr), IpAddr::V6(network)) => (addr.octets().to_vec(), network.octets().to_vec()),
+        _ => return false,
+    };
+    addr.iter().zip(network.iter()).enumerate().all(
+        |(i, (a, n))| {
+            let bits = (prefix_len as usize).saturating_sub(i * 8).min(8);
+            let mask = if bits == 0 { 0 } else { 0xffu8 << (8 - bits) };
+            a & mask == n & mask
+        },
+    )
+}
+
+/// Collects the forwarded addresses from left (the farthest) to right (the nearest).
+///
+/// `None` represents an entry which is not an IP address (e.g. `unknown` or
+/// an obfuscated identifier).
+fn forwarded_addrs(headers: &Headers) -> Vec<Option<IpAddr>> {
+    let mut addrs = Vec::new();
+
+    if let Some(raw) = headers.get_raw("Forwarded") {
+        for line in raw {
+            for elem in String::from_utf8_lossy(line).split(',') {
+                let node = elem.split(';')
+                    .filter_map(|pair| {
+                        let mut kv = pair.splitn(2, '=');
+                        match (kv.next(), kv.next()) {
+                            (Some(k), Some(v)) if k.trim().eq_ignore_ascii_case("for") => Some(v),
+                            _ => None,
+                        }
+                    })
+                    .next();
+                addrs.push(node.and_then(|node| parse_node(node.trim().trim_matches('"'))));
+            }
+        }
+    } else if let Some(raw) = headers.get_raw("X-Forwarded-For") {
+        for line in raw {
+            for node in String::from_utf8_lossy(line).split(',') {
+                addrs.push(parse_node(node.trim()));
+            }
+        }
+    }
+
+    addrs
+}
+
+/// Parses a node, which is either an IP address or a socket address
+/// (IPv6 addresses may be enclosed in brackets).
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            node.trim_left_matches('[').trim_right_matches(']').parse().ok()
+        })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use hyper::header::Headers;
+    use super::TrustedProxies;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn proxies() -> TrustedProxies {
+        TrustedProxies::default()
+            .with_network(ip("10.0.0.0"), 8)
+            .with_proxy(ip("2001:db8::1"))
+    }
+
+    #[test]
+    fn untrusted_peer() {
+        let mut headers = Headers::new();
+        headers.set_raw("X-Forwarded-For", "203.0.113.1");
+        assert_eq!(proxies().resolve(ip("192.0.2.1"), &headers), ip("192.0.2.1"));
+    }
+
+    #[test]
+    fn x_forwarded_for() {
+        let mut headers = Headers::new();
+        headers.set_raw("X-Forwarded-For", "198.51.100.7, 203.0.113.1, 10.1.2.3");
+        assert_eq!(proxies().resolve(ip("10.0.0.1"), &headers), ip("203.0.113.1"));
+    }
+
+    #[test]
+    fn forwarded() {
+        let mut headers = Headers::new();
+        headers.set_raw(
+            "Forwarded",
+            r#"for=198.51.100.7;proto=https, for="[2001:db8:cafe::17]:4711", for=2001:db8::1"#,
+        );
+        assert_eq!(proxies().resolve(ip("10.0.0.1"), &headers), ip("2001:db8:cafe::17"));
+    }
+
+    #[test]
+    fn obfuscated_node() {
+        let mut headers = Headers::new();
+        headers.set_raw("Forwarded", "for=198.51.100.7, for=_hidden, for=10.0.0.2");
+        assert_eq!(proxies().resolve(ip("10.0.0.1"), &headers), ip("10.0.0.2"));
+    }
+}
@@ -0,0 +1,371 @@
+//! Rate limiting of requests.
+
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::{future, Future};
+use futures::future::BoxFuture;
+use hyper::{Response, StatusCode};
+use hyper::header::Headers;
+use typemap::Key;
+
+use context::Context;
+use middleware::Middleware;
+use result::{AsyncResult, Failure};
+
+
+/// The error returned when a request exceeds its quota.
+#[derive(Debug)]
+pub struct RateLimited;
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "rate limited")
+    }
+}
+
+impl StdError for RateLimited {
+    fn description(&self) -> &str {
+        "rate limited"
+    }
+}
+
+
+/// The number of requests allowed per period, with a burst allowance.
+///
+/// The quota is enforced by GCRA (the generic cell rate algorithm), which
+/// behaves as a token bucket of `burst` tokens refilled at a constant rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    limit: u32,
+    period: Duration,
+    burst: u32,
+}
+
+impl Quota {
+    /// Creates a quota of `limit` requests per `period`.
+    ///
+    /// The burst size defaults to `limit`.
+    ///
+    /// # Panics
+    /// This method panics if `limit` is zero.
+    pub fn new(limit: u32, period: Duration) -> Self {
+        assert!(limit > 0, "the limit of a quota must be positive");
+        Quota {
+            limit,
+            period,
+            burst: limit,
+        }
+    }
+
+    /// Creates a quota of `limit` requests per second.
+    pub fn per_second(limit: u32) -> Self {
+        Quota::new(limit, Duration::from_secs(1))
+    }
+
+    /// Creates a quota of `limit` requests per minute.
+    pub fn per_minute(limit: u32) -> Self {
+        Quota::new(limit, Duration::from_secs(60))
+    }
+
+    /// Set the maximum number of requests accepted at once.
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+
+    /// Returns the number of requests allowed per period.
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    /// Returns the length of the period.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Returns the maximum number of requests accepted at once.
+    pub fn burst(&self) -> u32 {
+        self.burst
+    }
+
+    /// Returns the interval at which a token is refilled.
+    pub fn emission_interval(&self) -> Duration {
+        self.period / self.limit
+    }
+}
+
+
+/// The result of a check against a quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decision {
+    /// Whether the request is allowed.
+    pub allowed: bool,
+    /// The maximum number of requests accepted at once.
+    pub limit: u32,
+    /// The number of requests which can be sent immediately after this one.
+    pub remaining: u32,
+    /// The time until the quota is fully restored.
+    pub reset: Duration,
+    /// The time until the next request will be allowed, if this one was rejected.
+    pub retry_after: Option<Duration>,
+}
+
+impl Decision {
+    /// Computes a decision by GCRA from the theoretical arrival time stored for the key.
+    ///
+    /// Returns the decision and the theoretical arrival time to be stored, if the request is allowed.
+    pub fn gcra(quota: &Quota, tat: Option<Instant>, now: Instant) -> (Decision, Option<Instant>) {
+        let interval = quota.emission_interval();
+        let tolerance = interval * quota.burst;
+        let tat = tat.map_or(now, |tat| tat.max(now));
+        let new_tat = tat + interval;
+
+        if new_tat > now + tolerance {
+            let decision = Decision {
+                allowed: false,
+                limit: quota.burst,
+                remaining: 0,
+                reset: tat - now,
+                retry_after: Some(new_tat - (now + tolerance)),
+            };
+            return (decision, None);
+        }
+
+        let headroom = now + tolerance - new_tat;
+        let decision = Decision {
+            allowed: true,
+            limit: quota.burst,
+            remaining: div_duration(headroom, interval),
+            reset: new_tat - now,
+            retry_after: None,
+        };
+        (decision, Some(new_tat))
+    }
+
+    fn set_headers(&self, headers: &mut Headers) {
+        headers.set_raw("RateLimit-Limit", self.limit.to_string());
+        headers.set_raw("RateLimit-Remaining", self.remaining.to_string());
+        headers.set_raw("RateLimit-Reset", ceil_secs(self.reset).to_string());
+        if let Some(retry_after) = self.retry_after {
+            headers.set_raw("Retry-After", ceil_secs(retry_after).to_string());
+        }
+    }
+}
+
+fn div_duration(a: Duration, b: Duration) -> u32 {
+    let nanos = |d: Duration| d.as_secs() as u128 * 1_000_000_000 + u128::from(d.subsec_nanos());
+    match nanos(b) {
+        0 => 0,
+        b => (nanos(a) / b) as u32,
+    }
+}
+
+fn ceil_secs(d: Duration) -> u64 {
+    d.as_secs() + if d.subsec_nanos() > 0 { 1 } else { 0 }
+}
+
+
+/// A storage of the rate limiting state of each key.
+///
+/// Implement this trait to share the state between several servers
+/// (e.g. on Redis).
+pub trait Store: 'static + Send + Sync {
+    /// Checks a request of the key against the quota, and consumes a token if allowed.
+    fn acquire(&self, key: &str, quota: &Quota) -> BoxFuture<Decision, Failure>;
+}
+
+/// A `Store` which keeps the state in the memory of the process.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    state: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl MemoryStore {
+    const PRUNE_THRESHOLD: usize = 10_000;
+}
+
+impl Store for MemoryStore {
+    fn acquire(&self, key: &str, quota: &Quota) -> BoxFuture<Decision, Failure> {
+        let now = Instant::now();
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        // Entries in the past carry no information, and are dropped in order to bound the memory.
+        if state.len() >= Self::PRUNE_THRESHOLD {
+            state.retain(|_, tat| *tat > now);
+        }
+
+        let (decision, tat) = Decision::gcra(quota, state.get(key).cloned(), now);
+        if let Some(tat) = tat {
+            state.insert(key.to_owned(), tat);
+        }
+        future::ok(decision).boxed()
+    }
+}
+
+
+/// A function which determines the key a request is limited by.
+///
+/// Requests without a key (i.e. `None`) are not limited.
+pub trait KeyExtractor: 'static + Send + Sync {
+    fn key(&self, ctx: &Context) -> Option<String>;
+}
+
+impl<F> KeyExtractor for F
+where
+    F: 'static + Send + Sync + Fn(&Context) -> Option<String>,
+{
+    fn key(&self, ctx: &Context) -> Option<String> {
+        (*self)(ctx)
+    }
+}
+
+/// Limits requests by the IP address of the client.
+///
+/// See `Context::client_ip()`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ByClientIp;
+
+impl KeyExtractor for ByClientIp {
+    fn key(&self, ctx: &Context) -> Option<String> {
+        ctx.client_ip().map(|ip| ip.to_string())
+    }
+}
+
+/// Limits requests by a value stored in `ctx.ext` by previous middlewares
+/// (e.g. the authenticated user).
+pub struct ByExt<K>(PhantomData<fn() -> K>);
+
+impl<K> ByExt<K> {
+    pub fn new() -> Self {
+        ByExt(PhantomData)
+    }
+}
+
+impl<K> Default for ByExt<K> {
+    fn default() -> Self {
+        ByExt::new()
+    }
+}
+
+impl<K> KeyExtractor for ByExt<K>
+where
+    K: Key + 'static,
+    K::Value: fmt::Display + Send,
+{
+    fn key(&self, ctx: &Context) -> Option<String> {
+        ctx.ext.get::<K>().map(|value| value.to_string())
+    }
+}
+
+
+/// A middleware which rejects requests exceeding a quota with `429 Too Many Requests`.
+///
+/// The quota is applied per key, which is the client IP address by default.
+/// Both accepted and rejected responses have `RateLimit-Limit`, `RateLimit-Remaining`
+/// and `RateLimit-Reset` headers, and rejected responses also have `Retry-After`.
+pub struct RateLimit<M> {
+    inner: Arc<M>,
+    quota: Quota,
+    key: Box<KeyExtractor>,
+    store: Arc<Store>,
+}
+
+impl<M: Middleware> RateLimit<M> {
+    /// Creates a middleware which limits requests to the inner middleware, in memory.
+    pub fn new(inner: M, quota: Quota) -> Self {
+        RateLimit {
+            inner: Arc::new(inner),
+            quota,
+            key: Box::new(ByClientIp),
+            store: Arc::new(MemoryStore::default()),
+        }
+    }
+
+    /// Set the function which determines the key of each request.
+    pub fn with_key<K: KeyExtractor>(mut self, key: K) -> Self {
+        self.key = Box::new(key);
+        self
+    }
+
+    /// Set the storage of the state.
+    pub fn with_store<S: Store>(mut self, store: S) -> Self {
+        self.store = Arc::new(store);
+        self
+    }
+}
+
+impl<M: Middleware> Middleware for RateLimit<M> {
+    fn call(&self, ctx: Context) -> AsyncResult {
+        let key = match self.key.key(&ctx) {
+            Some(key) => key,
+            None => return self.inner.call(ctx),
+        };
+
+        let inner = self.inner.clone();
+        self.store
+            .acquire(&key, &self.quota)
+            .and_then(move |decision| {
+                if !decision.allowed {
+                    let mut res = Response::new()
+                        .with_status(StatusCode::TooManyRequests)
+                        .with_body("Too Many Requests");
+                    decision.set_headers(res.headers_mut());
+                    return future::err(Failure::from(RateLimited).with_response(res)).boxed();
+                }
+                inner
+                    .call(ctx)
+                    .then(move |result| match result {
+                        Ok(mut ctx) => {
+                            if let Some(ref mut res) = ctx.res {
+                                decision.set_headers(res.headers_mut());
+                            }
+                            Ok(ctx)
+                        }
+                        Err(mut failure) => {
+                            decision.set_headers(failure.response.headers_mut());
+                            Err(failure)
+                        }
+                    })
+                    .boxed()
+            })
+            .boxed()
+    }
+
+    fn after(&self, ctx: Context) -> AsyncResult {
+        self.inner.after(ctx)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::{Decision, Quota};
+
+    #[test]
+    fn gcra_burst_and_refill() {
+        let quota = Quota::per_second(2);
+        let now = Instant::now();
+
+        let (d1, tat) = Decision::gcra(&quota, None, now);
+        assert!(d1.allowed);
+        assert_eq!(d1.remaining, 1);
+        let (d2, tat) = Decision::gcra(&quota, tat, now);
+        assert!(d2.allowed);
+        assert_eq!(d2.remaining, 0);
+        let (d3, none) = Decision::gcra(&quota, tat, now);
+        assert!(!d3.allowed);
+        assert!(none.is_none());
+        assert_eq!(d3.retry_after, Some(Duration::from_millis(500)));
+
+        let (d4, _) = Decision::gcra(&quota, tat, now + Duration::from_millis(500));
+        assert!(d4.allowed);
+    }
+}
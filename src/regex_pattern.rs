@@ -65,6 +65,20 @@ impl OwnedCaptures {
     pub fn name(&self, name: &str) -> Option<&str> {
         self.names.get(name).and_then(|&i| self.get(i))
     }
+
+    /// Returns the matched groups except the whole match, with their names if any.
+    pub(crate) fn params(&self) -> Vec<(Option<String>, String)> {
+        let mut names = vec![None; self.matches.len()];
+        for (name, &i) in self.names.iter() {
+            names[i] = Some(name.clone());
+        }
+        names
+            .into_iter()
+            .enumerate()
+            .skip(1)
+            .filter_map(|(i, name)| self.get(i).map(|value| (name, value.to_owned())))
+            .collect()
+    }
 }
 
 impl Index<usize> for OwnedCaptures {
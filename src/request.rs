@@ -36,4 +36,9 @@ impl Request {
     pub fn take_body(&mut self) -> Option<Body> {
         self.body.take()
     }
+
+    /// Puts the request body back, e.g. after a middleware has buffered and inspected it.
+    pub fn set_body(&mut self, body: Body) {
+        self.body = Some(body);
+    }
 }
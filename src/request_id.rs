@@ -0,0 +1,128 @@
+//! Propagation of request IDs.
+
+use futures::Future;
+use rand::{self, Rng};
+use typemap::Key;
+
+use context::Context;
+use middleware::Middleware;
+use result::AsyncResult;
+
+
+/// The ID of a request, stored in `ctx.ext` by `RequestIdMiddleware`.
+///
+/// The ID is also stored in `failure.ext` if the request ends with a `Failure`,
+/// so that it can be used from the error handler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl Key for RequestId {
+    type Value = Self;
+}
+
+
+/// A middleware which assigns an ID to each request.
+///
+/// If the request has a valid `X-Request-Id` header, its value is used as the ID.
+/// Otherwise, a new random ID is generated.
+/// The ID is echoed in the response header of the same name.
+pub struct RequestIdMiddleware<M> {
+    inner: M,
+    header_name: String,
+}
+
+impl<M: Middleware> RequestIdMiddleware<M> {
+    /// Creates a middleware which assigns request IDs before calling the inner middleware.
+    pub fn new(inner: M) -> Self {
+        RequestIdMiddleware {
+            inner,
+            header_name: "X-Request-Id".to_owned(),
+        }
+    }
+
+    /// Set the name of the header to read and write the ID (e.g. `X-Correlation-Id`).
+    pub fn with_header_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.header_name = name.into();
+        self
+    }
+
+    fn assign<F>(&self, mut ctx: Context, f: F) -> AsyncResult
+    where
+        F: FnOnce(&M, Context) -> AsyncResult,
+    {
+        let id = ctx.req
+            .headers
+            .get_raw(&self.header_name)
+            .and_then(|raw| raw.one())
+            .and_then(|value| String::from_utf8(value.to_owned()).ok())
+            .filter(|id| is_valid(id))
+            .unwrap_or_else(generate);
+        ctx.ext.insert::<RequestId>(RequestId(id.clone()));
+
+        let header_name = self.header_name.clone();
+        f(&self.inner, ctx)
+            .then(move |result| match result {
+                Ok(mut ctx) => {
+                    if let Some(ref mut res) = ctx.res {
+                        res.headers_mut().set_raw(header_name, id);
+                    }
+                    Ok(ctx)
+                }
+                Err(mut failure) => {
+                    failure.response.headers_mut().set_raw(header_name, id.clone());
+                    failure.ext.insert::<RequestId>(RequestId(id));
+                    Err(failure)
+                }
+            })
+            .boxed()
+    }
+}
+
+impl<M: Middleware> Middleware for RequestIdMiddleware<M> {
+    fn call(&self, ctx: Context) -> AsyncResult {
+        self.assign(ctx, |inner, ctx| inner.call(ctx))
+    }
+
+    fn after(&self, ctx: Context) -> AsyncResult {
+        self.assign(ctx, |inner, ctx| inner.after(ctx))
+    }
+}
+
+
+/// Accepts only short, printable ASCII IDs in order to avoid header or log injection.
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 200 && id.bytes().all(|b| b > 0x20 && b < 0x7f)
+}
+
+/// Generates a random ID in the format of UUID version 4.
+fn generate() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        hex[0..4].concat(),
+        hex[4..6].concat(),
+        hex[6..8].concat(),
+        hex[8..10].concat(),
+        hex[10..16].concat()
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, is_valid};
+
+    #[test]
+    fn generated_id_is_valid() {
+        let id = generate();
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "4");
+        assert!(is_valid(&id));
+        assert!(!is_valid("foo\r\nSet-Cookie: x"));
+    }
+}
@@ -0,0 +1,155 @@
+//! Conversion of values into responses.
+
+use hyper::{Response, StatusCode};
+use hyper::header::{ContentType, Location};
+use serde::Serialize;
+use serde_json;
+
+use extract::Json;
+use result::Failure;
+
+
+/// A type which can be converted into a response.
+///
+/// A `Context` can be finished with any responder by `Context::respond()`.
+pub trait Responder {
+    fn into_response(self) -> Result<Response, Failure>;
+}
+
+impl Responder for Response {
+    fn into_response(self) -> Result<Response, Failure> {
+        Ok(self)
+    }
+}
+
+/// Responds with `200 OK` and the string as a plain text.
+impl Responder for String {
+    fn into_response(self) -> Result<Response, Failure> {
+        Ok(
+            Response::new()
+                .with_status(StatusCode::Ok)
+                .with_header(ContentType::plaintext())
+                .with_body(self),
+        )
+    }
+}
+
+/// Responds with `200 OK` and the string as a plain text.
+impl<'a> Responder for &'a str {
+    fn into_response(self) -> Result<Response, Failure> {
+        self.to_owned().into_response()
+    }
+}
+
+/// Overrides the status code of the response.
+impl<T: Responder> Responder for (StatusCode, T) {
+    fn into_response(self) -> Result<Response, Failure> {
+        let (status, inner) = self;
+        inner.into_response().map(|res| res.with_status(status))
+    }
+}
+
+/// Responds with the value, or fails with the error.
+impl<T, E> Responder for Result<T, E>
+where
+    T: Responder,
+    E: Into<Failure>,
+{
+    fn into_response(self) -> Result<Response, Failure> {
+        self.map_err(Into::into).and_then(Responder::into_response)
+    }
+}
+
+/// Responds with `200 OK` and the value serialized as JSON.
+impl<T: Serialize> Responder for Json<T> {
+    fn into_response(self) -> Result<Response, Failure> {
+        let body = serde_json::to_vec(&self.0)?;
+        Ok(
+            Response::new()
+                .with_status(StatusCode::Ok)
+                .with_header(ContentType::json())
+                .with_body(body),
+        )
+    }
+}
+
+/// Responds with `200 OK` and the value as JSON.
+impl Responder for serde_json::Value {
+    fn into_response(self) -> Result<Response, Failure> {
+        Json(self).into_response()
+    }
+}
+
+
+/// An HTML document, which responds with `200 OK` and `Content-Type: text/html`.
+pub struct Html<T>(pub T);
+
+impl<T: Into<String>> Responder for Html<T> {
+    fn into_response(self) -> Result<Response, Failure> {
+        Ok(
+            Response::new()
+                .with_status(StatusCode::Ok)
+                .with_header(ContentType::html())
+                .with_body(self.0.into()),
+        )
+    }
+}
+
+
+/// A redirection to another location.
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    status: StatusCode,
+    location: String,
+}
+
+impl Redirect {
+    /// Creates a redirection with `302 Found`.
+    pub fn to<S: Into<String>>(location: S) -> Self {
+        Redirect::with_status(StatusCode::Found, location)
+    }
+
+    /// Creates a redirection with `301 Moved Permanently`.
+    pub fn permanent<S: Into<String>>(location: S) -> Self {
+        Redirect::with_status(StatusCode::MovedPermanently, location)
+    }
+
+    /// Creates a redirection with `303 See Other`, e.g. after a form is posted.
+    ///
+    /// The client follows it with `GET`.
+    pub fn see_other<S: Into<String>>(location: S) -> Self {
+        Redirect::with_status(StatusCode::SeeOther, location)
+    }
+
+    /// Creates a redirection with `307 Temporary Redirect`.
+    ///
+    /// Unlike `302 Found`, the client must not change the method and the body.
+    pub fn temporary<S: Into<String>>(location: S) -> Self {
+        Redirect::with_status(StatusCode::TemporaryRedirect, location)
+    }
+
+    /// Creates a redirection with `308 Permanent Redirect`.
+    ///
+    /// Unlike `301 Moved Permanently`, the client must not change the method and the body.
+    pub fn permanent_redirect<S: Into<String>>(location: S) -> Self {
+        Redirect::with_status(StatusCode::PermanentRedirect, location)
+    }
+
+    /// Creates a redirection with given status code.
+    pub fn with_status<S: Into<String>>(status: StatusCode, location: S) -> Self {
+        Redirect {
+            status,
+            location: location.into(),
+        }
+    }
+}
+
+impl Responder for Redirect {
+    fn into_response(self) -> Result<Response, Failure> {
+        Ok(
+            Response::new()
+                .with_status(self.status)
+                .with_header(Location::new(self.location)),
+        )
+    }
+}
@@ -1,6 +1,9 @@
+use std::any::Any;
 use std::error::Error as StdError;
+use std::fmt;
 use hyper::{Response, StatusCode};
 use futures::future::BoxFuture;
+use typemap::SendMap;
 use context::Context;
 
 /// Asynchronous result type
@@ -11,6 +14,9 @@ pub type AsyncResult = BoxFuture<Context, Failure>;
 pub struct Failure {
     pub err: Box<StdError + Send + 'static>,
     pub response: Response,
+    /// A typemap to carry request-scoped values (e.g. the request ID)
+    /// to the error handler, since the context is dropped on failure.
+    pub ext: SendMap,
 }
 
 impl<E: StdError + 'static + Send> From<E> for Failure {
@@ -21,6 +27,7 @@ impl<E: StdError + 'static + Send> From<E> for Failure {
             response: Response::new()
                 .with_status(StatusCode::InternalServerError)
                 .with_body(body),
+            ext: SendMap::custom(),
         }
     }
 }
@@ -33,6 +40,58 @@ impl Failure {
 }
 
 
+/// The error of a `Failure` caused by a panic of a middleware.
+#[derive(Debug)]
+pub struct Panicked(String);
+
+impl Panicked {
+    pub(crate) fn from_payload(payload: Box<Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&'static str>() {
+                Ok(message) => (*message).to_owned(),
+                Err(_) => "Box<Any>".to_owned(),
+            },
+        };
+        Panicked(message)
+    }
+
+    /// Returns the message of the panic.
+    pub fn message(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Panicked {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "panicked at '{}'", self.0)
+    }
+}
+
+impl StdError for Panicked {
+    fn description(&self) -> &str {
+        "panicked"
+    }
+}
+
+
+/// A handler which builds the response returned to the client from a `Failure`.
+///
+/// The default handler returns `failure.response` as it is.
+pub trait ErrorHandler: 'static + Send + Sync {
+    fn handle(&self, failure: Failure) -> Response;
+}
+
+impl<F> ErrorHandler for F
+where
+    F: 'static + Send + Sync + Fn(Failure) -> Response,
+{
+    fn handle(&self, failure: Failure) -> Response {
+        (*self)(failure)
+    }
+}
+
+
 #[macro_export]
 macro_rules! try_f {
     ($e:expr) => (match $e {
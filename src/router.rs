@@ -3,15 +3,19 @@ use std::collections::HashMap;
 use std::fmt;
 use std::error::Error as StdError;
 use std::sync::Arc;
+use std::time::Duration;
 use futures::{future, Future};
 use hyper::server::Response;
-use hyper::{Method, StatusCode};
+use hyper::{Get, Head, Method, StatusCode};
 use regex::Regex;
+use typemap::Key;
 
 use context::Context;
 use middleware::Middleware;
 use result::{AsyncResult, Failure};
 use regex_pattern::{RegexPattern, OwnedCaptures};
+use responder::Redirect;
+use timeout::with_deadline;
 
 
 #[derive(Debug)]
@@ -30,15 +34,59 @@ impl StdError for NoRoute {
 }
 
 
-struct Route {
+/// The pattern of the route matched by `Router`, as it was registered.
+///
+/// The value is stored in `ctx.ext`, and also in `failure.ext` if the
+/// matched middleware returns a `Failure`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchedRoute(pub String);
+
+impl Key for MatchedRoute {
+    type Value = Self;
+}
+
+
+/// The policy of `Router` on a trailing slash of request paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// The trailing slash is optional, i.e. both `/a` and `/a/` match the route `/a`.
+    Lenient,
+    /// The path must match the route as it was registered.
+    Strict,
+    /// A path which differs from a route only by the trailing slash is redirected
+    /// to the registered one, with `301 Moved Permanently` for `GET` and `HEAD`
+    /// and `308 Permanent Redirect` for other methods.
+    Redirect,
+}
+
+impl Default for TrailingSlash {
+    fn default() -> Self {
+        TrailingSlash::Lenient
+    }
+}
+
+
+pub(crate) struct Route {
+    source: String,
     pattern: RegexPattern,
+    strict: RegexPattern,
     middleware: Arc<Middleware>,
+    timeout: Option<Duration>,
+}
+
+impl Route {
+    /// Returns the pattern of this route, as it was registered.
+    pub(crate) fn source(&self) -> &str {
+        &self.source
+    }
 }
 
 
 #[derive(Default)]
 pub struct Router {
     routes: HashMap<Method, Vec<Route>>,
+    timeout: Option<Duration>,
+    trailing_slash: TrailingSlash,
 }
 
 impl Router {
@@ -48,14 +96,19 @@ impl Router {
         S: AsRef<str>,
         M: Middleware,
     {
+        let source = pattern.as_ref().to_owned();
+        let strict = Regex::new(&strict_pattern(pattern.as_ref())).unwrap();
         let pattern = normalize_pattern(pattern.as_ref());
         let pattern = Regex::new(&pattern).unwrap();
         self.routes
             .entry(method)
             .or_insert(Vec::new())
             .push(Route {
+                source,
                 pattern: pattern.into(),
+                strict: strict.into(),
                 middleware: Arc::new(middleware),
+                timeout: None,
             });
         self
     }
@@ -72,29 +125,126 @@ impl Router {
         self
     }
 
+    /// Set the deadline applied to the middlewares of all routes.
+    ///
+    /// If a matched middleware does not complete in time, the request fails
+    /// with `504 Gateway Timeout`. See also `Timeout`.
+    pub fn set_timeout(&mut self, duration: Duration) -> &mut Self {
+        self.timeout = Some(duration);
+        self
+    }
+
+    /// Set the deadline applied to the middlewares of all routes, and return itself.
+    pub fn with_timeout(mut self, duration: Duration) -> Self {
+        self.set_timeout(duration);
+        self
+    }
+
+    /// Override the deadline of the route registered with the given method and pattern.
+    ///
+    /// # Panics
+    /// This method panics if no such route has been added.
+    pub fn set_route_timeout<S>(&mut self, method: &Method, pattern: S, duration: Duration) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        let route = self.routes
+            .get_mut(method)
+            .and_then(|routes| routes.iter_mut().find(|r| r.source == pattern.as_ref()))
+            .unwrap_or_else(|| panic!("no route: {} {}", method, pattern.as_ref()));
+        route.timeout = Some(duration);
+        self
+    }
+
+    /// Override the deadline of the route, and return itself.
+    pub fn with_route_timeout<S>(mut self, method: &Method, pattern: S, duration: Duration) -> Self
+    where
+        S: AsRef<str>,
+    {
+        self.set_route_timeout(method, pattern, duration);
+        self
+    }
+
+    /// Set the policy on a trailing slash of request paths.
+    pub fn set_trailing_slash(&mut self, policy: TrailingSlash) -> &mut Self {
+        self.trailing_slash = policy;
+        self
+    }
+
+    /// Set the policy on a trailing slash of request paths, and return itself.
+    pub fn with_trailing_slash(mut self, policy: TrailingSlash) -> Self {
+        self.set_trailing_slash(policy);
+        self
+    }
+
     pub(crate) fn recognize(
         &self,
         method: &Method,
         path: &str,
-    ) -> Result<(&Middleware, OwnedCaptures), NoRoute> {
+    ) -> Result<(&Route, OwnedCaptures), NoRoute> {
+        let strict = self.trailing_slash != TrailingSlash::Lenient;
+        self.find(method, path, strict)
+    }
+
+    fn find(&self, method: &Method, path: &str, strict: bool) -> Result<(&Route, OwnedCaptures), NoRoute> {
         let routes = self.routes.get(method).ok_or(NoRoute)?;
         for route in routes {
-            if let Some(caps) = route.pattern.owned_captures(path) {
-                return Ok((&*route.middleware, caps));
+            let pattern = if strict { &route.strict } else { &route.pattern };
+            if let Some(caps) = pattern.owned_captures(path) {
+                return Ok((route, caps));
             }
         }
         Err(NoRoute)
     }
+
+    /// Returns the canonical path of a request path whose trailing slash differs from a route.
+    fn canonical_path(&self, method: &Method, path: &str) -> Option<String> {
+        let canonical = if path.len() > 1 && path.ends_with('/') {
+            path.trim_right_matches('/').to_owned()
+        } else {
+            format!("{}/", path)
+        };
+        self.find(method, &canonical, true).ok().map(|_| canonical)
+    }
 }
 
 impl Middleware for Router {
     fn call(&self, mut ctx: Context) -> AsyncResult {
         match self.recognize(&ctx.req.method, &ctx.req.path()) {
-            Ok((middleware, cap)) => {
+            Ok((route, cap)) => {
+                let matched = MatchedRoute(route.source().to_owned());
                 ctx.ext.insert::<OwnedCaptures>(cap);
-                middleware.call(ctx)
+                ctx.ext.insert::<MatchedRoute>(matched.clone());
+                let handle = ctx.handle();
+                let mut future = route.middleware.call(ctx);
+                if let Some(duration) = route.timeout.or(self.timeout) {
+                    future = with_deadline(future, handle, duration, || {
+                        Response::new()
+                            .with_status(StatusCode::GatewayTimeout)
+                            .with_body("Gateway Timeout")
+                    });
+                }
+                future
+                    .map_err(move |mut failure| {
+                        failure.ext.insert::<MatchedRoute>(matched);
+                        failure
+                    })
+                    .boxed()
             }
             Err(err) => {
+                if self.trailing_slash == TrailingSlash::Redirect {
+                    if let Some(path) = self.canonical_path(&ctx.req.method, ctx.req.path()) {
+                        let location = match ctx.req.uri.query() {
+                            Some(query) => format!("{}?{}", path, query),
+                            None => path,
+                        };
+                        let redirect = match ctx.req.method {
+                            Get | Head => Redirect::permanent(location),
+                            _ => Redirect::permanent_redirect(location),
+                        };
+                        return ctx.respond(redirect);
+                    }
+                }
                 future::err(Failure::from(err).with_response(
                     Response::new().with_status(
                         StatusCode::NotFound,
@@ -107,6 +257,17 @@ impl Middleware for Router {
 
 
 
+fn strict_pattern(pattern: &str) -> Cow<str> {
+    let pattern = pattern
+        .trim()
+        .trim_left_matches("^")
+        .trim_right_matches("$");
+    match pattern {
+        "" => "^/$".into(),
+        s => format!("^{}$", s).into(),
+    }
+}
+
 fn normalize_pattern(pattern: &str) -> Cow<str> {
     let pattern = pattern
         .trim()
@@ -121,7 +282,7 @@ fn normalize_pattern(pattern: &str) -> Cow<str> {
 
 #[cfg(test)]
 mod tests {
-    use super::normalize_pattern;
+    use super::{normalize_pattern, strict_pattern};
 
     #[test]
     fn normalize_cases() {
@@ -129,4 +290,11 @@ mod tests {
         assert_eq!(normalize_pattern("/path/to"), "^/path/to/?$");
         assert_eq!(normalize_pattern("/path/to/"), "^/path/to/?$");
     }
+
+    #[test]
+    fn strict_cases() {
+        assert_eq!(strict_pattern("/"), "^/$");
+        assert_eq!(strict_pattern("/path/to"), "^/path/to$");
+        assert_eq!(strict_pattern("/path/to/"), "^/path/to/$");
+    }
 }
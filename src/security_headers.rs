@@ -0,0 +1,279 @@
+//! Hardening response headers, including Content Security Policy.
+
+use std::time::Duration;
+
+use base64;
+use futures::Future;
+use hyper::header::Headers;
+use rand::{self, Rng};
+use typemap::Key;
+
+use context::Context;
+use middleware::Middleware;
+use result::AsyncResult;
+
+
+/// The nonce of the Content Security Policy of a request, stored in `ctx.ext` by `SecurityHeaders`.
+///
+/// Put it in the `nonce` attribute of inline `<script>` and `<style>` elements.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CspNonce(pub String);
+
+impl Key for CspNonce {
+    type Value = Self;
+}
+
+impl Context {
+    /// Returns the nonce of the Content Security Policy for the request, if any.
+    pub fn csp_nonce(&self) -> Option<&str> {
+        self.ext.get::<CspNonce>().map(|nonce| nonce.0.as_str())
+    }
+}
+
+
+/// A builder of `Content-Security-Policy`.
+///
+/// ```ignore
+/// ContentSecurityPolicy::new()
+///     .with("default-src", &["'self'"])
+///     .with("img-src", &["'self'", "https://images.example.com"])
+///     .with_nonce("script-src")
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ContentSecurityPolicy {
+    directives: Vec<(String, Vec<String>)>,
+    nonce_directives: Vec<String>,
+    report_only: bool,
+}
+
+impl ContentSecurityPolicy {
+    /// Creates an empty policy.
+    pub fn new() -> Self {
+        ContentSecurityPolicy::default()
+    }
+
+    /// Adds the sources to the directive.
+    pub fn with(mut self, directive: &str, sources: &[&str]) -> Self {
+        let sources = sources.iter().map(|s| s.to_string());
+        match self.directives.iter().position(|&(ref name, _)| name == directive) {
+            Some(i) => self.directives[i].1.extend(sources),
+            None => self.directives.push((directive.to_owned(), sources.collect())),
+        }
+        self
+    }
+
+    /// Adds the nonce generated for each request to the sources of the directive
+    /// (e.g. `script-src`).
+    pub fn with_nonce(mut self, directive: &str) -> Self {
+        if !self.directives.iter().any(|&(ref name, _)| name == directive) {
+            self.directives.push((directive.to_owned(), vec![]));
+        }
+        self.nonce_directives.push(directive.to_owned());
+        self
+    }
+
+    /// Sends the policy as `Content-Security-Policy-Report-Only`, which reports
+    /// violations without blocking them.
+    pub fn report_only(mut self) -> Self {
+        self.report_only = true;
+        self
+    }
+
+    fn uses_nonce(&self) -> bool {
+        !self.nonce_directives.is_empty()
+    }
+
+    fn header_name(&self) -> &'static str {
+        if self.report_only {
+            "Content-Security-Policy-Report-Only"
+        } else {
+            "Content-Security-Policy"
+        }
+    }
+
+    fn to_header_value(&self, nonce: Option<&str>) -> String {
+        let directives: Vec<String> = self.directives
+            .iter()
+            .map(|&(ref name, ref sources)| {
+                let mut value = name.clone();
+                for source in sources {
+                    value.push(' ');
+                    value.push_str(source);
+                }
+                if let Some(nonce) = nonce.filter(|_| self.nonce_directives.contains(name)) {
+                    value.push_str(&format!(" 'nonce-{}'", nonce));
+                }
+                value
+            })
+            .collect();
+        directives.join("; ")
+    }
+}
+
+
+/// A middleware which sets hardening headers on the responses of the inner middleware,
+/// including the ones of failures.
+///
+/// By default, it sets the following headers. Headers already set by the inner
+/// middleware are kept as they are.
+///
+/// * `Strict-Transport-Security: max-age=31536000; includeSubDomains`
+/// * `X-Content-Type-Options: nosniff`
+/// * `X-Frame-Options: DENY`
+/// * `Referrer-Policy: strict-origin-when-cross-origin`
+///
+/// `Content-Security-Policy` depends on the application, and is set only if configured
+/// by `with_csp()`.
+pub struct SecurityHeaders<M> {
+    inner: M,
+    headers: Vec<(&'static str, String)>,
+    csp: Option<ContentSecurityPolicy>,
+}
+
+impl<M: Middleware> SecurityHeaders<M> {
+    /// Creates a middleware with the default set of headers.
+    pub fn new(inner: M) -> Self {
+        SecurityHeaders {
+            inner,
+            headers: vec![
+                ("Strict-Transport-Security", "max-age=31536000; includeSubDomains".to_owned()),
+                ("X-Content-Type-Options", "nosniff".to_owned()),
+                ("X-Frame-Options", "DENY".to_owned()),
+                ("Referrer-Policy", "strict-origin-when-cross-origin".to_owned()),
+            ],
+            csp: None,
+        }
+    }
+
+    /// Set `Strict-Transport-Security`, which makes browsers use only HTTPS for the host.
+    pub fn with_hsts(self, max_age: Duration, include_subdomains: bool) -> Self {
+        let mut value = format!("max-age={}", max_age.as_secs());
+        if include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        self.with_header("Strict-Transport-Security", value)
+    }
+
+    /// Set `X-Frame-Options` (e.g. `SAMEORIGIN`).
+    pub fn with_frame_options<S: Into<String>>(self, value: S) -> Self {
+        self.with_header("X-Frame-Options", value)
+    }
+
+    /// Set `Referrer-Policy` (e.g. `no-referrer`).
+    pub fn with_referrer_policy<S: Into<String>>(self, value: S) -> Self {
+        self.with_header("Referrer-Policy", value)
+    }
+
+    /// Set the Content Security Policy.
+    ///
+    /// If the policy uses nonces, a new nonce is stored in `ctx.ext` as `CspNonce`
+    /// before the inner middleware is called.
+    pub fn with_csp(mut self, csp: ContentSecurityPolicy) -> Self {
+        self.csp = Some(csp);
+        self
+    }
+
+    /// Set an arbitrary header.
+    pub fn with_header<S: Into<String>>(mut self, name: &'static str, value: S) -> Self {
+        self.headers.retain(|&(n, _)| !n.eq_ignore_ascii_case(name));
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    /// Stop setting the header (e.g. `Strict-Transport-Security` on a plain-text development server).
+    pub fn without(mut self, name: &str) -> Self {
+        self.headers.retain(|&(n, _)| !n.eq_ignore_ascii_case(name));
+        self
+    }
+
+    fn apply<F>(&self, mut ctx: Context, f: F) -> AsyncResult
+    where
+        F: FnOnce(&M, Context) -> AsyncResult,
+    {
+        let nonce = match self.csp {
+            Some(ref csp) if csp.uses_nonce() => {
+                let nonce = ctx.ext
+                    .get::<CspNonce>()
+                    .map(|nonce| nonce.0.clone())
+                    .unwrap_or_else(generate_nonce);
+                ctx.ext.insert::<CspNonce>(CspNonce(nonce.clone()));
+                Some(nonce)
+            }
+            _ => None,
+        };
+
+        let mut headers = self.headers.clone();
+        if let Some(ref csp) = self.csp {
+            headers.push((csp.header_name(), csp.to_header_value(nonce.as_ref().map(|s| s.as_str()))));
+        }
+
+        f(&self.inner, ctx)
+            .then(move |result| match result {
+                Ok(mut ctx) => {
+                    if let Some(ref mut res) = ctx.res {
+                        set_missing(res.headers_mut(), headers);
+                    }
+                    Ok(ctx)
+                }
+                Err(mut failure) => {
+                    set_missing(failure.response.headers_mut(), headers);
+                    Err(failure)
+                }
+            })
+            .boxed()
+    }
+}
+
+impl<M: Middleware> Middleware for SecurityHeaders<M> {
+    fn call(&self, ctx: Context) -> AsyncResult {
+        self.apply(ctx, |inner, ctx| inner.call(ctx))
+    }
+
+    fn after(&self, ctx: Context) -> AsyncResult {
+        self.apply(ctx, |inner, ctx| inner.after(ctx))
+    }
+}
+
+
+fn set_missing(headers: &mut Headers, values: Vec<(&'static str, String)>) {
+    for (name, value) in values {
+        if headers.get_raw(name).is_none() {
+            headers.set_raw(name, value);
+        }
+    }
+}
+
+/// Generates a nonce from 128 random bits.
+fn generate_nonce() -> String {
+    base64::encode(&random_bytes(16))
+}
+
+/// Generates random bytes for secrets, preferring the randomness of the OS.
+pub(crate) fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; len];
+    match rand::OsRng::new() {
+        Ok(mut rng) => rng.fill_bytes(&mut bytes),
+        Err(_) => rand::thread_rng().fill_bytes(&mut bytes),
+    }
+    bytes
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csp_header_value() {
+        let csp = ContentSecurityPolicy::new()
+            .with("default-src", &["'self'"])
+            .with_nonce("script-src")
+            .with("script-src", &["'strict-dynamic'"])
+            .with("object-src", &["'none'"]);
+        assert_eq!(
+            csp.to_header_value(Some("abc")),
+            "default-src 'self'; script-src 'strict-dynamic' 'nonce-abc'; object-src 'none'"
+        );
+        assert_eq!(csp.header_name(), "Content-Security-Policy");
+    }
+}
@@ -0,0 +1,157 @@
+use std::net::SocketAddr;
+#[cfg(feature = "tls")]
+use std::io;
+
+use futures::{future, Async, Future, Stream};
+use futures::future::Either;
+use hyper::{Chunk, Error as HyperError};
+use hyper::server::Http;
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::{Core, Handle};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::io::shutdown;
+
+use connection::Connection;
+use susanoo::{Susanoo, SusanooService};
+#[cfg(feature = "tls")]
+use tls::TlsConfig;
+
+
+enum Acceptor {
+    Plain,
+    #[cfg(feature = "tls")]
+    Tls(TlsConfig),
+}
+
+
+/// An HTTP server, which serves a `Susanoo` application on an event loop.
+pub struct Server {
+    core: Core,
+    listener: TcpListener,
+    protocol: Http<Chunk>,
+    susanoo: Susanoo,
+    acceptor: Acceptor,
+}
+
+impl Server {
+    pub(crate) fn bind(addr: &SocketAddr, susanoo: Susanoo) -> Result<Self, HyperError> {
+        let core = Core::new()?;
+        let listener = TcpListener::bind(addr, &core.handle())?;
+        Ok(Server {
+            core,
+            listener,
+            protocol: Http::new(),
+            susanoo,
+            acceptor: Acceptor::Plain,
+        })
+    }
+
+    #[cfg(feature = "tls")]
+    pub(crate) fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.acceptor = Acceptor::Tls(tls);
+        self
+    }
+
+    /// Returns the local address that this server is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr, HyperError> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Returns a handle to the underlying event loop.
+    pub fn handle(&self) -> Handle {
+        self.core.handle()
+    }
+
+    /// Execute this server infinitely.
+    pub fn run(self) -> Result<(), HyperError> {
+        self.run_until(future::empty())
+    }
+
+    /// Execute this server until the given future, `shutdown_signal`, resolves.
+    pub fn run_until<F>(self, shutdown_signal: F) -> Result<(), HyperError>
+    where
+        F: Future<Item = (), Error = ()>,
+    {
+        let Server {
+            mut core,
+            listener,
+            protocol,
+            susanoo,
+            acceptor,
+        } = self;
+        let handle = core.handle();
+
+        let serve = listener.incoming().map_err(HyperError::from).for_each(
+            |(socket, peer_addr)| {
+                let id = susanoo.next_connection_id();
+                let local_addr = socket.local_addr().ok();
+                let remote = handle.remote().clone();
+                match acceptor {
+                    Acceptor::Plain => {
+                        let conn = Connection::plain(id)
+                            .with_addrs(peer_addr, local_addr)
+                            .with_remote(remote);
+                        let service = susanoo.service(conn);
+                        handle.spawn(serve_connection(&protocol, socket, service, &handle).then(
+                            |_| Ok(()),
+                        ));
+                    }
+                    #[cfg(feature = "tls")]
+                    Acceptor::Tls(ref tls) => {
+                        let protocol = protocol.clone();
+                        let susanoo = susanoo.clone();
+                        let conn_handle = handle.clone();
+                        let conn = tls.accept(socket).and_then(move |stream| {
+                            let conn = Connection::secure(id, stream.sni_hostname().map(Into::into))
+                                .with_addrs(peer_addr, local_addr)
+                                .with_remote(remote);
+                            let service = susanoo.service(conn);
+                            serve_connection(&protocol, stream, service, &conn_handle).map_err(|err| {
+                                io::Error::new(io::ErrorKind::Other, err)
+                            })
+                        });
+                        handle.spawn(conn.then(|_| Ok(())));
+                    }
+                }
+                Ok(())
+            },
+        );
+
+        let shutdown_signal = shutdown_signal.then(|_| Ok(()));
+        match core.run(shutdown_signal.select(serve)) {
+            Ok(_) => Ok(()),
+            Err((err, _)) => Err(err),
+        }
+    }
+}
+
+
+/// Serves HTTP on the connection, and hands it over to the WebSocket handler
+/// if a response has upgraded it.
+fn serve_connection<I>(
+    protocol: &Http<Chunk>,
+    io: I,
+    service: SusanooService,
+    handle: &Handle,
+) -> Box<Future<Item = (), Error = HyperError>>
+where
+    I: AsyncRead + AsyncWrite + 'static,
+{
+    let mut conn = Some(protocol.serve_connection(io, service));
+    let handle = handle.clone();
+    let serve = future::poll_fn(move || {
+        match conn.as_mut().expect("polled after completion").poll_without_shutdown()? {
+            Async::Ready(()) => Ok(Async::Ready(conn.take().unwrap().into_parts())),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    });
+    Box::new(serve.and_then(move |parts| {
+        match parts.service.take_upgrade() {
+            Some(upgrade) => {
+                handle.spawn(upgrade.run(parts.io, parts.read_buf));
+                Either::A(future::ok(()))
+            }
+            None => Either::B(shutdown(parts.io).map(|_| ()).map_err(HyperError::from)),
+        }
+    }))
+}
@@ -0,0 +1,219 @@
+//! Server-Sent Events.
+
+use std::fmt;
+use std::time::Duration;
+
+use futures::{future, Async, Future, Poll, Stream};
+use hyper::{Response, StatusCode};
+use hyper::header::{CacheControl, CacheDirective, ContentType};
+use hyper::mime;
+use tokio_core::reactor::Interval;
+
+use context::Context;
+use result::{AsyncResult, Failure};
+use streaming::{spawn_body, NoEventLoop, StreamedBody};
+
+
+/// A message sent to the client of an event stream.
+#[derive(Debug, Clone, Default)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: String,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    /// Creates an event with the data. It may contain multiple lines.
+    pub fn new<S: Into<String>>(data: S) -> Self {
+        Event {
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the ID of the event, which the client sends back as `Last-Event-ID` on reconnect.
+    pub fn with_id<S: Into<String>>(mut self, id: S) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Sets the type of the event, dispatched to the listeners of that name on the client.
+    pub fn with_event<S: Into<String>>(mut self, event: S) -> Self {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// Sets the time the client waits before reconnecting.
+    pub fn with_retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+/// Formats the event in the wire format of `text/event-stream`.
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref id) = self.id {
+            writeln!(f, "id: {}", single_line(id))?;
+        }
+        if let Some(ref event) = self.event {
+            writeln!(f, "event: {}", single_line(event))?;
+        }
+        if let Some(retry) = self.retry {
+            let millis = retry.as_secs() * 1000 + u64::from(retry.subsec_millis());
+            writeln!(f, "retry: {}", millis)?;
+        }
+        // Clients end lines at `\r\n`, `\r` or `\n`, and a trailing line break is an empty line.
+        let lines = self.data.split("\r\n").flat_map(|line| line.split(is_line_break));
+        for line in lines {
+            writeln!(f, "data: {}", line)?;
+        }
+        writeln!(f)
+    }
+}
+
+fn single_line(s: &str) -> &str {
+    s.split(is_line_break).next().unwrap_or("")
+}
+
+fn is_line_break(c: char) -> bool {
+    c == '\r' || c == '\n'
+}
+
+
+/// A response which sends a stream of events and holds the connection open until it ends.
+pub struct Sse<S> {
+    events: S,
+    keep_alive: Option<Duration>,
+}
+
+impl<S> Sse<S>
+where
+    S: Stream<Item = Event> + 'static,
+    S::Error: Into<Failure>,
+{
+    /// Creates an event stream, which sends a keep-alive comment every 15 seconds.
+    pub fn new(events: S) -> Self {
+        Sse {
+            events,
+            keep_alive: Some(Duration::from_secs(15)),
+        }
+    }
+
+    /// Sets the interval of keep-alive comments.
+    ///
+    /// They keep proxies from closing an idle connection, and detect a disconnected client.
+    pub fn with_keep_alive(mut self, interval: Duration) -> Self {
+        self.keep_alive = Some(interval);
+        self
+    }
+
+    /// Disables keep-alive comments.
+    pub fn without_keep_alive(mut self) -> Self {
+        self.keep_alive = None;
+        self
+    }
+}
+
+
+impl Context {
+    /// Finishes the context with `200 OK` and the event stream.
+    ///
+    /// This requires the event loop of `Server`, which drives the stream and the keep-alive timer.
+    pub fn sse<S>(mut self, sse: Sse<S>) -> AsyncResult
+    where
+        S: Stream<Item = Event> + 'static,
+        S::Error: Into<Failure>,
+    {
+        let handle = match self.handle() {
+            Some(handle) => handle,
+            None => return future::err(NoEventLoop.into()).boxed(),
+        };
+        let interval = match sse.keep_alive.map(|duration| Interval::new(duration, &handle)) {
+            Some(Ok(interval)) => Some(interval),
+            Some(Err(err)) => return future::err(err.into()).boxed(),
+            None => None,
+        };
+        let events = KeepAlive {
+            events: sse.events.map(|event| event.to_string()).map_err(Into::into),
+            interval,
+        };
+
+        let res = Response::new()
+            .with_status(StatusCode::Ok)
+            .with_header(ContentType(mime::TEXT_EVENT_STREAM))
+            .with_header(CacheControl(vec![CacheDirective::NoCache]))
+            .with_body(spawn_body(&handle, events));
+        self.ext.insert::<StreamedBody>(StreamedBody);
+        self.finish(res)
+    }
+
+    /// Returns the value of `Last-Event-ID`, sent by a client reconnecting to an event stream.
+    pub fn last_event_id(&self) -> Option<String> {
+        self.req
+            .headers
+            .get_raw("Last-Event-ID")
+            .and_then(|raw| raw.one())
+            .and_then(|value| String::from_utf8(value.to_owned()).ok())
+    }
+}
+
+
+/// Interleaves keep-alive comments with the events, and ends with them.
+struct KeepAlive<S> {
+    events: S,
+    interval: Option<Interval>,
+}
+
+impl<S> Stream for KeepAlive<S>
+where
+    S: Stream<Item = String, Error = Failure>,
+{
+    type Item = String;
+    type Error = Failure;
+
+    fn poll(&mut self) -> Poll<Option<String>, Failure> {
+        if let Async::Ready(event) = self.events.poll()? {
+            return Ok(Async::Ready(event));
+        }
+        if let Some(ref mut interval) = self.interval {
+            if let Async::Ready(Some(())) = interval.poll()? {
+                return Ok(Async::Ready(Some(":\n\n".to_owned())));
+            }
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_event() {
+        assert_eq!(Event::new("hello").to_string(), "data: hello\n\n");
+        assert_eq!(
+            Event::new("a\nb")
+                .with_id("42")
+                .with_event("update")
+                .with_retry(Duration::from_millis(1500))
+                .to_string(),
+            "id: 42\nevent: update\nretry: 1500\ndata: a\ndata: b\n\n"
+        );
+        assert_eq!(Event::new("").to_string(), "data: \n\n");
+        assert_eq!(Event::new("a\n").to_string(), "data: a\ndata: \n\n");
+        assert_eq!(
+            Event::new("a\r\nb\rc\r").to_string(),
+            "data: a\ndata: b\ndata: c\ndata: \n\n"
+        );
+        assert_eq!(
+            Event::new("x")
+                .with_id("1\rdata: forged")
+                .with_event("update\r\nid: 2")
+                .to_string(),
+            "id: 1\nevent: update\ndata: x\n\n"
+        );
+    }
+}
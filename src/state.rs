@@ -0,0 +1,96 @@
+//! Application-wide shared state.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use futures::{future, Future};
+use futures::future::BoxFuture;
+use hyper::{Response, StatusCode};
+
+use context::Context;
+use extract::FromContext;
+use result::Failure;
+
+
+/// The values registered by `Susanoo::with_state()`, indexed by their types.
+#[derive(Clone, Default)]
+pub(crate) struct AppState {
+    values: HashMap<TypeId, Arc<Any + Send + Sync>>,
+}
+
+impl AppState {
+    pub(crate) fn insert<T: Any + Send + Sync>(&mut self, value: T) {
+        self.values.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    pub(crate) fn get<T: Any + Send + Sync>(&self) -> Result<&T, MissingState> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+            .ok_or_else(MissingState::of::<T>)
+    }
+
+    pub(crate) fn get_arc<T: Any + Send + Sync>(&self) -> Result<Arc<T>, MissingState> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.clone().downcast().ok())
+            .ok_or_else(MissingState::of::<T>)
+    }
+}
+
+
+/// The error returned when no state of the requested type has been registered.
+#[derive(Debug)]
+pub struct MissingState(&'static str);
+
+impl MissingState {
+    fn of<T>() -> Self {
+        MissingState(::std::any::type_name::<T>())
+    }
+}
+
+impl fmt::Display for MissingState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "no application state of type `{}`; register it by Susanoo::with_state()",
+            self.0
+        )
+    }
+}
+
+impl StdError for MissingState {
+    fn description(&self) -> &str {
+        "missing application state"
+    }
+}
+
+
+/// An application state registered by `Susanoo::with_state()`, used as an argument of handlers.
+///
+/// The request fails with `500 Internal Server Error` if the state has not been registered.
+pub struct State<T>(pub Arc<T>);
+
+impl<T> Deref for State<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Any + Send + Sync> FromContext for State<T> {
+    fn from_context(ctx: &mut Context) -> BoxFuture<Self, Failure> {
+        let result = ctx.state_arc::<T>().map(State).map_err(|err| {
+            Failure::from(err).with_response(
+                Response::new()
+                    .with_status(StatusCode::InternalServerError)
+                    .with_body("Internal Server Error"),
+            )
+        });
+        future::result(result).boxed()
+    }
+}
@@ -0,0 +1,119 @@
+//! Streaming response bodies.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::panic::AssertUnwindSafe;
+
+use futures::{future, Future, Sink, Stream};
+use hyper::{Body, Chunk, Error as HyperError, Response};
+use tokio_core::reactor::Handle;
+use typemap::Key;
+
+use context::Context;
+use result::{AsyncResult, Failure, Panicked};
+
+
+/// The error returned when a request which needs the event loop is not handled by `Server`.
+#[derive(Debug)]
+pub struct NoEventLoop;
+
+impl fmt::Display for NoEventLoop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the request is not handled on the event loop of Server")
+    }
+}
+
+impl StdError for NoEventLoop {
+    fn description(&self) -> &str {
+        "no event loop"
+    }
+}
+
+
+/// A marker of the responses whose body is streamed, which must not be buffered.
+pub(crate) struct StreamedBody;
+
+impl Key for StreamedBody {
+    type Value = Self;
+}
+
+
+impl Context {
+    /// Finishes the context with the response, whose body is sent from a stream of chunks
+    /// (e.g. the rows of a large CSV export).
+    ///
+    /// The body is sent with chunked transfer encoding unless the response has
+    /// `Content-Length`. The stream is polled only as fast as the client receives
+    /// the chunks, and is dropped if the connection is closed. If the stream fails or panics,
+    /// the connection is aborted since the response has already been started.
+    pub fn stream<S>(mut self, res: Response, body: S) -> AsyncResult
+    where
+        S: Stream + 'static,
+        S::Item: Into<Chunk>,
+        S::Error: Into<Failure>,
+    {
+        let handle = match self.handle() {
+            Some(handle) => handle,
+            None => return future::err(NoEventLoop.into()).boxed(),
+        };
+        let body = spawn_body(&handle, body);
+        self.ext.insert::<StreamedBody>(StreamedBody);
+        self.finish(res.with_body(body))
+    }
+}
+
+
+/// Creates a body fed by the stream, which is driven by the event loop.
+///
+/// The stream runs as a separate task, so its panics end the body with an error
+/// instead of unwinding through the event loop.
+pub(crate) fn spawn_body<S>(handle: &Handle, stream: S) -> Body
+where
+    S: Stream + 'static,
+    S::Item: Into<Chunk>,
+    S::Error: Into<Failure>,
+{
+    let (tx, body) = Body::pair();
+    let chunks = AssertUnwindSafe(stream)
+        .catch_unwind()
+        .then(|result| -> Result<Result<Chunk, HyperError>, ()> {
+            let failure: Failure = match result {
+                Ok(Ok(chunk)) => return Ok(Ok(chunk.into())),
+                Ok(Err(err)) => err.into(),
+                Err(payload) => Panicked::from_payload(payload).into(),
+            };
+            Ok(Err(io::Error::new(io::ErrorKind::Other, failure.err.to_string()).into()))
+        })
+        .forward(tx.sink_map_err(|_| ()));
+    handle.spawn(chunks.then(|_| Ok(())));
+    body
+}
+
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+    use tokio_core::reactor::Core;
+    use super::*;
+
+    #[test]
+    fn panic_ends_body_with_error() {
+        let mut core = Core::new().unwrap();
+        let rows = (0..3).map(|i| {
+            if i == 2 {
+                panic!("row {}", i);
+            }
+            format!("{}\n", i)
+        });
+        let body = spawn_body(&core.handle(), stream::iter_ok::<_, Failure>(rows));
+
+        let mut received = Vec::new();
+        let result = core.run(body.for_each(|chunk| {
+            received.extend_from_slice(&chunk);
+            Ok(())
+        }));
+        assert!(result.is_err());
+        assert_eq!(received, b"0\n1\n");
+    }
+}
@@ -1,24 +1,41 @@
+use std::any::Any;
 use std::io;
-use std::sync::Arc;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use futures::Future;
+use futures::{future, Future};
 use futures::future::BoxFuture;
 use hyper::Error as HyperError;
-use hyper::{StatusCode, Server, Chunk};
-use hyper::server::{Http, Service, NewService, Response};
+use hyper::StatusCode;
+use hyper::server::{Service, NewService, Response};
 use hyper::server::Request;
 
+use blocking::BlockingPool;
+use connection::Connection;
 use context::Context;
 use middleware::Middleware;
+use result::{ErrorHandler, Failure, Panicked};
+use server::Server;
+use state::AppState;
+use websocket::OnUpgrade;
+#[cfg(feature = "tls")]
+use tls::TlsConfig;
 
 
 /// Internal state of server
+#[derive(Clone)]
 pub(crate) struct ServerInner {
     middleware: Arc<Middleware>,
+    error_handler: Arc<ErrorHandler>,
+    blocking: Arc<BlockingPool>,
+    state: Arc<AppState>,
+    next_connection_id: Arc<AtomicUsize>,
 }
 
 
 /// Root object of the application.
+#[derive(Clone)]
 pub struct Susanoo {
     inner: Arc<ServerInner>,
 }
@@ -26,13 +43,63 @@ pub struct Susanoo {
 impl Susanoo {
     /// Creates an empty instance of the server.
     pub fn new<M: Middleware>(middleware: M) -> Self {
-        Susanoo { inner: Arc::new(ServerInner { middleware: Arc::new(middleware) }) }
+        Susanoo {
+            inner: Arc::new(ServerInner {
+                middleware: Arc::new(middleware),
+                error_handler: Arc::new(|failure: Failure| failure.response),
+                blocking: Arc::new(BlockingPool::new(None)),
+                state: Arc::new(AppState::default()),
+                next_connection_id: Arc::new(AtomicUsize::new(0)),
+            }),
+        }
+    }
+
+    /// Set the handler to build responses from failures.
+    pub fn with_error_handler<H: ErrorHandler>(mut self, handler: H) -> Self {
+        Arc::make_mut(&mut self.inner).error_handler = Arc::new(handler);
+        self
+    }
+
+    /// Register a value shared by the whole application (e.g. a connection pool),
+    /// which can be obtained by `Context::state()` or the `State` extractor.
+    ///
+    /// A value registered with the same type replaces the previous one.
+    pub fn with_state<T: Any + Send + Sync>(mut self, value: T) -> Self {
+        Arc::make_mut(&mut Arc::make_mut(&mut self.inner).state).insert(value);
+        self
+    }
+
+    /// Set the number of threads which run the functions passed to `Context::spawn_blocking()`.
+    ///
+    /// By default, the number of CPUs is used. The threads are started on first use.
+    pub fn with_blocking_threads(mut self, size: usize) -> Self {
+        Arc::make_mut(&mut self.inner).blocking = Arc::new(BlockingPool::new(Some(size)));
+        self
     }
 
     /// Create server.
-    pub fn into_server(self, addr: &str) -> Result<Server<Self, ::hyper::Body>, HyperError> {
+    pub fn into_server(self, addr: &str) -> Result<Server, HyperError> {
         let addr = addr.parse().unwrap();
-        Http::<Chunk>::new().bind(&addr, self)
+        Server::bind(&addr, self)
+    }
+
+    /// Create server which serves HTTPS, with given TLS configuration.
+    #[cfg(feature = "tls")]
+    pub fn into_tls_server(self, addr: &str, tls: TlsConfig) -> Result<Server, HyperError> {
+        let addr = addr.parse().unwrap();
+        Server::bind(&addr, self).map(|server| server.with_tls(tls))
+    }
+
+    pub(crate) fn next_connection_id(&self) -> usize {
+        self.inner.next_connection_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub(crate) fn service(&self, conn: Connection) -> SusanooService {
+        SusanooService {
+            inner: self.inner.clone(),
+            conn: Arc::new(conn),
+            upgrade: Arc::new(Mutex::new(None)),
+        }
     }
 }
 
@@ -43,7 +110,7 @@ impl NewService for Susanoo {
     type Instance = SusanooService;
 
     fn new_service(&self) -> io::Result<Self::Instance> {
-        Ok(SusanooService { inner: self.inner.clone() })
+        Ok(self.service(Connection::plain(self.next_connection_id())))
     }
 }
 
@@ -51,6 +118,15 @@ impl NewService for Susanoo {
 /// An asynchronous task executed by hyper.
 pub struct SusanooService {
     inner: Arc<ServerInner>,
+    conn: Arc<Connection>,
+    upgrade: Arc<Mutex<Option<OnUpgrade>>>,
+}
+
+impl SusanooService {
+    /// Takes the handler of the connection upgraded by the last response.
+    pub(crate) fn take_upgrade(&self) -> Option<OnUpgrade> {
+        self.upgrade.lock().unwrap().take()
+    }
 }
 
 impl Service for SusanooService {
@@ -60,20 +136,48 @@ impl Service for SusanooService {
     type Future = BoxFuture<Response, HyperError>;
 
     fn call(&self, req: Request) -> Self::Future {
-        let ctx = Context::from_hyper(req);
+        let ctx = Context::new(req, self.conn.clone())
+            .with_blocking_pool(self.inner.blocking.clone())
+            .with_state(self.inner.state.clone());
+        let error_handler = self.inner.error_handler.clone();
+        let upgrade = self.upgrade.clone();
+
+        // Panics of middlewares, either in `call` or while polling the returned future,
+        // are converted into failures so that they do not tear down the event loop.
+        let middleware = &self.inner.middleware;
+        let future = match panic::catch_unwind(AssertUnwindSafe(|| middleware.call(ctx))) {
+            Ok(future) => future,
+            Err(payload) => future::err(panicked(payload)).boxed(),
+        };
 
-        self.inner
-            .middleware
-            .call(ctx)
+        AssertUnwindSafe(future)
+            .catch_unwind()
             .then(|result| match result {
-                Ok(ctx) => {
-                    match ctx.res {
-                        Some(res) => Ok(res),
+                Ok(result) => result,
+                Err(payload) => Err(panicked(payload)),
+            })
+            .then(move |result| match result {
+                Ok(mut ctx) => {
+                    match ctx.res.take() {
+                        Some(res) => {
+                            if res.status() == StatusCode::SwitchingProtocols {
+                                *upgrade.lock().unwrap() = ctx.ext.remove::<OnUpgrade>();
+                            }
+                            Ok(res)
+                        }
                         None => Ok(Response::new().with_status(StatusCode::NotFound)),
                     }
                 }
-                Err(failure) => Ok(failure.response),
+                Err(failure) => Ok(error_handler.handle(failure)),
             })
             .boxed()
     }
 }
+
+fn panicked(payload: Box<Any + Send>) -> Failure {
+    Failure::from(Panicked::from_payload(payload)).with_response(
+        Response::new()
+            .with_status(StatusCode::InternalServerError)
+            .with_body("Internal Server Error"),
+    )
+}
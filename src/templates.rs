@@ -0,0 +1,253 @@
+//! Rendering of templates (requires the feature `templates`).
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::path::Path;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use hyper::{Response, StatusCode};
+use hyper::header::ContentType;
+use serde::Serialize;
+use serde_json::{self, Map, Value};
+use tera::{Context as TeraContext, Tera};
+use typemap::Key;
+
+use context::Context;
+use result::Failure;
+
+
+/// The error returned when a template cannot be loaded or rendered.
+#[derive(Debug)]
+pub struct TemplateError(String);
+
+impl TemplateError {
+    /// Creates an error with the message.
+    pub fn new<S: Into<String>>(message: S) -> Self {
+        TemplateError(message.into())
+    }
+
+    /// Creates an error from the error and its sources, which often carry the details
+    /// (e.g. the line of the syntax error).
+    pub fn from_error(err: &StdError) -> Self {
+        let mut message = err.to_string();
+        let mut source = err.source();
+        while let Some(err) = source {
+            message.push_str(": ");
+            message.push_str(&err.to_string());
+            source = err.source();
+        }
+        TemplateError(message)
+    }
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl StdError for TemplateError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+
+/// A template engine.
+pub trait Renderer: Send + Sync + 'static {
+    /// Renders the template with the data, which is always a JSON object.
+    fn render(&self, name: &str, data: &Map<String, Value>) -> Result<String, TemplateError>;
+}
+
+
+/// The template engine of the application, used by `Context::render()`.
+///
+/// ```ignore
+/// let templates = Templates::new(TeraRenderer::from_dir("templates")?.with_dev_mode(true));
+/// let susanoo = Susanoo::new(router).with_state(templates);
+/// ```
+pub struct Templates {
+    renderer: Box<Renderer>,
+}
+
+impl Templates {
+    /// Creates a state which renders templates by the renderer.
+    pub fn new<R: Renderer>(renderer: R) -> Self {
+        Templates {
+            renderer: Box::new(renderer),
+        }
+    }
+}
+
+
+/// The values available to all templates rendered for a request, stored in `ctx.ext`.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateGlobals(pub Map<String, Value>);
+
+impl Key for TemplateGlobals {
+    type Value = Self;
+}
+
+
+impl Context {
+    /// Adds a value available to all templates rendered for the request
+    /// (e.g. the current user, set by an authentication middleware).
+    pub fn set_template_global<T: Serialize>(&mut self, name: &str, value: &T) -> Result<(), Failure> {
+        let value = serde_json::to_value(value).map_err(|err| render_failure(TemplateError::from_error(&err)))?;
+        self.ext
+            .entry::<TemplateGlobals>()
+            .or_insert_with(TemplateGlobals::default)
+            .0
+            .insert(name.to_owned(), value);
+        Ok(())
+    }
+
+    /// Renders the template with the data, into an HTML response.
+    ///
+    /// The data must serialize into a map. The CSRF token, the nonce of the Content
+    /// Security Policy and the values added by `set_template_global()` are merged into
+    /// it as `csrf_token`, `csp_nonce` and their names, unless the data has the same keys.
+    ///
+    /// The request fails with `500 Internal Server Error` if the template engine is not
+    /// registered by `Susanoo::with_state()`, or rendering fails.
+    pub fn render<T: Serialize>(&self, name: &str, data: &T) -> Result<Response, Failure> {
+        let templates = self.state::<Templates>().map_err(render_failure)?;
+
+        let mut data = match serde_json::to_value(data) {
+            Ok(Value::Object(map)) => map,
+            Ok(Value::Null) => Map::new(),
+            Ok(_) => {
+                return Err(render_failure(TemplateError::new(
+                    "the data of a template must serialize into a map",
+                )))
+            }
+            Err(err) => return Err(render_failure(TemplateError::from_error(&err))),
+        };
+        merge(&mut data, self.template_globals());
+
+        let body = templates
+            .renderer
+            .render(name, &data)
+            .map_err(render_failure)?;
+        Ok(Response::new()
+            .with_status(StatusCode::Ok)
+            .with_header(ContentType::html())
+            .with_body(body))
+    }
+
+    fn template_globals(&self) -> Map<String, Value> {
+        let mut globals = Map::new();
+        if let Some(token) = self.csrf_token() {
+            globals.insert("csrf_token".to_owned(), Value::from(token));
+        }
+        if let Some(nonce) = self.csp_nonce() {
+            globals.insert("csp_nonce".to_owned(), Value::from(nonce));
+        }
+        if let Some(&TemplateGlobals(ref values)) = self.ext.get::<TemplateGlobals>() {
+            globals.extend(values.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        globals
+    }
+}
+
+/// Inserts the globals into the data, without overwriting its values.
+fn merge(data: &mut Map<String, Value>, globals: Map<String, Value>) {
+    for (name, value) in globals {
+        data.entry(name).or_insert(value);
+    }
+}
+
+fn render_failure<E: StdError + Send + 'static>(err: E) -> Failure {
+    Failure::from(err).with_response(
+        Response::new()
+            .with_status(StatusCode::InternalServerError)
+            .with_body("Internal Server Error"),
+    )
+}
+
+
+/// A renderer backed by Tera.
+pub struct TeraRenderer {
+    tera: RwLock<Tera>,
+    dev_mode: bool,
+}
+
+impl TeraRenderer {
+    /// Creates a renderer with the templates already loaded into `tera`.
+    pub fn new(tera: Tera) -> Self {
+        TeraRenderer {
+            tera: RwLock::new(tera),
+            dev_mode: false,
+        }
+    }
+
+    /// Creates a renderer with all the templates under the directory,
+    /// named by their paths relative to it.
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<Self, TemplateError> {
+        let glob = dir.as_ref().join("**").join("*");
+        let glob = glob.to_str()
+            .ok_or_else(|| TemplateError::new("the path of the templates is not valid UTF-8"))?;
+        Tera::new(glob)
+            .map(TeraRenderer::new)
+            .map_err(|err| TemplateError::from_error(&err))
+    }
+
+    /// Set whether the templates are reloaded from the directory on every render,
+    /// so that changes are visible without restarting the server.
+    ///
+    /// It only applies to templates loaded by `from_dir()`, and should be disabled in production.
+    pub fn with_dev_mode(mut self, dev_mode: bool) -> Self {
+        self.dev_mode = dev_mode;
+        self
+    }
+
+    fn read(&self) -> RwLockReadGuard<Tera> {
+        match self.tera.read() {
+            Ok(tera) => tera,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    fn write(&self) -> RwLockWriteGuard<Tera> {
+        match self.tera.write() {
+            Ok(tera) => tera,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl Renderer for TeraRenderer {
+    fn render(&self, name: &str, data: &Map<String, Value>) -> Result<String, TemplateError> {
+        if self.dev_mode {
+            self.write()
+                .full_reload()
+                .map_err(|err| TemplateError::from_error(&err))?;
+        }
+        let context = TeraContext::from_value(Value::Object(data.clone()))
+            .map_err(|err| TemplateError::from_error(&err))?;
+        self.read()
+            .render(name, &context)
+            .map_err(|err| TemplateError::from_error(&err))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globals_do_not_overwrite_data() {
+        let mut data = Map::new();
+        data.insert("title".to_owned(), Value::from("Home"));
+        data.insert("csrf_token".to_owned(), Value::from("explicit"));
+        let mut globals = Map::new();
+        globals.insert("csrf_token".to_owned(), Value::from("global"));
+        globals.insert("current_user".to_owned(), Value::from("alice"));
+
+        merge(&mut data, globals);
+        assert_eq!(data["title"], "Home");
+        assert_eq!(data["csrf_token"], "explicit");
+        assert_eq!(data["current_user"], "alice");
+    }
+}
@@ -0,0 +1,127 @@
+//! Deadlines of request handling.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::time::Duration;
+
+use futures::{future, Future};
+use futures::future::Either;
+use hyper::{Response, StatusCode};
+use tokio_core::reactor::{Handle, Timeout as ReactorTimeout};
+
+use context::Context;
+use middleware::Middleware;
+use result::{AsyncResult, Failure};
+
+
+/// The error returned when the handling of a request exceeds its deadline.
+#[derive(Debug)]
+pub struct TimedOut(pub Duration);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "timed out after {:?}", self.0)
+    }
+}
+
+impl StdError for TimedOut {
+    fn description(&self) -> &str {
+        "timed out"
+    }
+}
+
+
+/// A middleware which fails with `504 Gateway Timeout` if the inner middleware
+/// does not complete within the given duration.
+///
+/// The deadline is driven by the event loop of `Server`. If the request was
+/// not accepted by `Server`, the inner middleware is called without a deadline.
+pub struct Timeout<M> {
+    inner: M,
+    duration: Duration,
+    status: StatusCode,
+    body: String,
+}
+
+impl<M: Middleware> Timeout<M> {
+    /// Creates a middleware which applies the deadline to the inner middleware.
+    pub fn new(inner: M, duration: Duration) -> Self {
+        Timeout {
+            inner,
+            duration,
+            status: StatusCode::GatewayTimeout,
+            body: "Gateway Timeout".to_owned(),
+        }
+    }
+
+    /// Set the status code of the response returned on timeout
+    /// (e.g. `503 Service Unavailable`).
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Set the body of the response returned on timeout.
+    pub fn with_body<S: Into<String>>(mut self, body: S) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    fn deadline<F>(&self, ctx: Context, f: F) -> AsyncResult
+    where
+        F: FnOnce(&M, Context) -> AsyncResult,
+    {
+        let handle = ctx.handle();
+        let status = self.status;
+        let body = self.body.clone();
+        with_deadline(f(&self.inner, ctx), handle, self.duration, move || {
+            Response::new().with_status(status).with_body(body)
+        })
+    }
+}
+
+impl<M: Middleware> Middleware for Timeout<M> {
+    fn call(&self, ctx: Context) -> AsyncResult {
+        self.deadline(ctx, |inner, ctx| inner.call(ctx))
+    }
+
+    fn after(&self, ctx: Context) -> AsyncResult {
+        self.deadline(ctx, |inner, ctx| inner.after(ctx))
+    }
+}
+
+
+/// Races a future against a timer on the event loop.
+///
+/// When the timer fires first, the future is dropped and the result
+/// becomes a `Failure` with the response built by `on_timeout`.
+pub(crate) fn with_deadline<F>(
+    future: AsyncResult,
+    handle: Option<Handle>,
+    duration: Duration,
+    on_timeout: F,
+) -> AsyncResult
+where
+    F: FnOnce() -> Response + Send + 'static,
+{
+    let handle = match handle {
+        Some(handle) => handle,
+        None => return future,
+    };
+    let timeout = match ReactorTimeout::new(duration, &handle) {
+        Ok(timeout) => timeout,
+        Err(err) => return future::err(err.into()).boxed(),
+    };
+
+    future
+        .select2(timeout)
+        .then(move |result| match result {
+            Ok(Either::A((ctx, _))) => Ok(ctx),
+            Ok(Either::B(((), _))) => {
+                Err(Failure::from(TimedOut(duration)).with_response(on_timeout()))
+            }
+            Err(Either::A((failure, _))) => Err(failure),
+            Err(Either::B((err, _))) => Err(err.into()),
+        })
+        .boxed()
+}
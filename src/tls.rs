@@ -0,0 +1,331 @@
+//! TLS termination, based on rustls.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use futures::{Async, Future, Poll};
+use rustls::internal::pemfile;
+use rustls::{ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig, ServerSession, Session};
+use rustls::sign::{self, CertifiedKey};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+
+/// TLS settings of a server.
+///
+/// The certificate chain and the private key are read from PEM files,
+/// and can be reloaded while the server is running by calling `reload()`
+/// on a clone of the configuration.
+#[derive(Clone)]
+pub struct TlsConfig {
+    config: Arc<ServerConfig>,
+    certs: Arc<ReloadableCert>,
+}
+
+impl TlsConfig {
+    /// Creates a configuration from a PEM-encoded certificate chain and private key.
+    ///
+    /// The private key may be encoded in either PKCS#8 or PKCS#1 (RSA) format.
+    pub fn from_pem_files<P, Q>(cert_path: P, key_path: Q) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let certs = Arc::new(ReloadableCert::open(
+            cert_path.as_ref().to_owned(),
+            key_path.as_ref().to_owned(),
+        )?);
+
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.cert_resolver = certs.clone();
+        config.set_protocols(&[b"http/1.1".to_vec()]);
+
+        Ok(TlsConfig {
+            config: Arc::new(config),
+            certs,
+        })
+    }
+
+    /// Reads the certificate chain and the private key from the files again.
+    ///
+    /// Connections accepted after this call use the new certificates.
+    /// If the files are invalid, the current certificates are kept as they are.
+    pub fn reload(&self) -> io::Result<()> {
+        self.certs.reload()
+    }
+
+    pub(crate) fn accept<S: Read + Write>(&self, io: S) -> Accept<S> {
+        Accept {
+            stream: Some(TlsStream {
+                io,
+                session: ServerSession::new(&self.config),
+                eof: false,
+                closing: false,
+            }),
+        }
+    }
+}
+
+
+struct ReloadableCert {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<CertifiedKey>,
+}
+
+impl ReloadableCert {
+    fn open(cert_path: PathBuf, key_path: PathBuf) -> io::Result<Self> {
+        let current = load_certified_key(&cert_path, &key_path)?;
+        Ok(ReloadableCert {
+            cert_path,
+            key_path,
+            current: RwLock::new(current),
+        })
+    }
+
+    fn reload(&self) -> io::Result<()> {
+        let certified_key = load_certified_key(&self.cert_path, &self.key_path)?;
+        let mut current = self.current.write().map_err(|_| {
+            io::Error::new(io::ErrorKind::Other, "certificate lock is poisoned")
+        })?;
+        *current = certified_key;
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for ReloadableCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<CertifiedKey> {
+        self.current.read().ok().map(|current| current.clone())
+    }
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> io::Result<CertifiedKey> {
+    let certs = pemfile::certs(&mut BufReader::new(File::open(cert_path)?))
+        .map_err(|_| invalid_data("failed to parse the certificate chain"))?;
+    if certs.is_empty() {
+        return Err(invalid_data("no certificate found"));
+    }
+
+    let mut keys = pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(key_path)?))
+        .map_err(|_| invalid_data("failed to parse the private key"))?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut BufReader::new(File::open(key_path)?))
+            .map_err(|_| invalid_data("failed to parse the private key"))?;
+    }
+    let key = keys.into_iter().next().ok_or_else(
+        || invalid_data("no private key found"),
+    )?;
+    let key = sign::any_supported_type(&key).map_err(
+        |_| invalid_data("unsupported private key"),
+    )?;
+
+    Ok(CertifiedKey::new(certs, Arc::new(key)))
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+
+/// A stream encrypted by TLS, on the server side.
+pub struct TlsStream<S> {
+    io: S,
+    session: ServerSession,
+    eof: bool,
+    closing: bool,
+}
+
+impl<S> TlsStream<S> {
+    /// Returns the server name sent by the client via SNI, if any.
+    pub fn sni_hostname(&self) -> Option<&str> {
+        self.session.get_sni_hostname()
+    }
+}
+
+impl<S: Read + Write> TlsStream<S> {
+    fn read_tls(&mut self) -> io::Result<usize> {
+        let n = self.session.read_tls(&mut self.io)?;
+        if let Err(err) = self.session.process_new_packets() {
+            // try to send the alert to the peer before failing.
+            let _ = self.session.write_tls(&mut self.io);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+        }
+        Ok(n)
+    }
+
+    fn write_tls(&mut self) -> io::Result<()> {
+        while self.session.wants_write() {
+            self.session.write_tls(&mut self.io)?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Read for TlsStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.write_tls() {
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                res => res?,
+            }
+
+            match self.session.read(buf) {
+                Ok(0) if !self.eof && !buf.is_empty() => {}
+                Err(ref err) if err.kind() == io::ErrorKind::ConnectionAborted => return Ok(0),
+                res => return res,
+            }
+
+            if self.read_tls()? == 0 {
+                self.eof = true;
+            }
+        }
+    }
+}
+
+impl<S: Read + Write> Write for TlsStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Send the pending records before encrypting more data, so that
+        // the buffer in the session does not grow without bound.
+        self.write_tls()?;
+        let n = self.session.write(buf)?;
+        match self.write_tls() {
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            res => res?,
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.session.flush()?;
+        self.write_tls()?;
+        self.io.flush()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> AsyncRead for TlsStream<S> {}
+
+impl<S: AsyncRead + AsyncWrite> AsyncWrite for TlsStream<S> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        if !self.closing {
+            self.session.send_close_notify();
+            self.closing = true;
+        }
+        try_nb!(self.flush());
+        self.io.shutdown()
+    }
+}
+
+
+/// A future which completes the TLS handshake.
+pub(crate) struct Accept<S> {
+    stream: Option<TlsStream<S>>,
+}
+
+impl<S: Read + Write> Future for Accept<S> {
+    type Item = TlsStream<S>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        {
+            let stream = self.stream.as_mut().expect("cannot poll Accept twice");
+            while stream.session.is_handshaking() {
+                if stream.session.wants_write() {
+                    try_nb!(stream.write_tls());
+                } else if try_nb!(stream.read_tls()) == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed during the TLS handshake",
+                    ));
+                }
+            }
+            try_nb!(stream.write_tls());
+        }
+        Ok(Async::Ready(self.stream.take().unwrap()))
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use rustls::{ClientConfig, ClientSession, Stream};
+    use webpki::DNSNameRef;
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls").join(name)
+    }
+
+    /// Connects to a server of the configuration as `localhost`, and returns the SNI hostname
+    /// seen by the server and the certificate seen by the client.
+    fn connect(tls: &TlsConfig) -> (Option<String>, Vec<u8>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let tls = tls.clone();
+        let server = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let mut stream = tls.accept(socket).wait().unwrap();
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).unwrap();
+            stream.write_all(&buf).unwrap();
+            stream.flush().unwrap();
+            stream.sni_hostname().map(ToOwned::to_owned)
+        });
+
+        let mut config = ClientConfig::new();
+        let mut ca = BufReader::new(File::open(fixture("ca.pem")).unwrap());
+        config.root_store.add_pem_file(&mut ca).unwrap();
+        let name = DNSNameRef::try_from_ascii_str("localhost").unwrap();
+        let mut session = ClientSession::new(&Arc::new(config), name);
+        let mut socket = TcpStream::connect(addr).unwrap();
+        {
+            let mut stream = Stream::new(&mut session, &mut socket);
+            stream.write_all(b"ping").unwrap();
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"ping");
+        }
+        let cert = session.get_peer_certificates().unwrap()[0].0.clone();
+        (server.join().unwrap(), cert)
+    }
+
+    fn der(cert_path: &Path) -> Vec<u8> {
+        let certs = pemfile::certs(&mut BufReader::new(File::open(cert_path).unwrap())).unwrap();
+        certs[0].0.clone()
+    }
+
+    #[test]
+    fn handshake() {
+        let tls = TlsConfig::from_pem_files(fixture("cert.pem"), fixture("key.pem")).unwrap();
+        let (sni_hostname, cert) = connect(&tls);
+        assert_eq!(sni_hostname.as_ref().map(|s| &**s), Some("localhost"));
+        assert_eq!(cert, der(&fixture("cert.pem")));
+    }
+
+    #[test]
+    fn reload_certificates() {
+        let dir = ::std::env::temp_dir().join(format!("susanoo-tls-reload-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        fs::copy(fixture("cert.pem"), &cert_path).unwrap();
+        fs::copy(fixture("key.pem"), &key_path).unwrap();
+        let tls = TlsConfig::from_pem_files(&cert_path, &key_path).unwrap();
+
+        fs::copy(fixture("cert2.pem"), &cert_path).unwrap();
+        fs::copy(fixture("key2.pem"), &key_path).unwrap();
+        tls.reload().unwrap();
+        assert_eq!(connect(&tls).1, der(&fixture("cert2.pem")));
+
+        // Invalid files keep the current certificates.
+        fs::write(&key_path, "").unwrap();
+        assert!(tls.reload().is_err());
+        assert_eq!(connect(&tls).1, der(&fixture("cert2.pem")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,205 @@
+//! Framing of WebSocket messages (RFC 6455, section 5).
+
+use std::io;
+
+use bytes::{BufMut, BytesMut};
+use tokio_codec::{Decoder, Encoder};
+
+
+/// The maximum length of a frame payload accepted from clients.
+pub(crate) const MAX_PAYLOAD_LEN: u64 = 16 * 1024 * 1024;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OpCode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl OpCode {
+    fn from_u8(bits: u8) -> Option<Self> {
+        match bits {
+            0x0 => Some(OpCode::Continuation),
+            0x1 => Some(OpCode::Text),
+            0x2 => Some(OpCode::Binary),
+            0x8 => Some(OpCode::Close),
+            0x9 => Some(OpCode::Ping),
+            0xA => Some(OpCode::Pong),
+            _ => None,
+        }
+    }
+
+    fn as_u8(&self) -> u8 {
+        match *self {
+            OpCode::Continuation => 0x0,
+            OpCode::Text => 0x1,
+            OpCode::Binary => 0x2,
+            OpCode::Close => 0x8,
+            OpCode::Ping => 0x9,
+            OpCode::Pong => 0xA,
+        }
+    }
+
+    pub(crate) fn is_control(&self) -> bool {
+        self.as_u8() & 0x8 != 0
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Frame {
+    pub(crate) fin: bool,
+    pub(crate) opcode: OpCode,
+    pub(crate) payload: Vec<u8>,
+}
+
+impl Frame {
+    pub(crate) fn new(opcode: OpCode, payload: Vec<u8>) -> Self {
+        Frame {
+            fin: true,
+            opcode,
+            payload,
+        }
+    }
+}
+
+
+/// A codec of the frames sent by clients and the ones sent by the server.
+///
+/// Frames from clients must be masked, and the ones from the server are never masked.
+#[derive(Debug, Default)]
+pub(crate) struct FrameCodec;
+
+impl Decoder for FrameCodec {
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, io::Error> {
+        if src.len() < 2 {
+            return Ok(None);
+        }
+        let (b0, b1) = (src[0], src[1]);
+        if b0 & 0x70 != 0 {
+            return Err(protocol_error("reserved bits must be zero"));
+        }
+        let fin = b0 & 0x80 != 0;
+        let opcode = OpCode::from_u8(b0 & 0x0F).ok_or_else(|| protocol_error("unknown opcode"))?;
+        if b1 & 0x80 == 0 {
+            return Err(protocol_error("frames from the client must be masked"));
+        }
+
+        let (payload_len, offset) = match b1 & 0x7F {
+            126 => {
+                if src.len() < 4 {
+                    return Ok(None);
+                }
+                ((u64::from(src[2]) << 8) | u64::from(src[3]), 4)
+            }
+            127 => {
+                if src.len() < 10 {
+                    return Ok(None);
+                }
+                let len = src[2..10].iter().fold(0, |len, &b| (len << 8) | u64::from(b));
+                (len, 10)
+            }
+            len => (u64::from(len), 2),
+        };
+        if opcode.is_control() && (!fin || payload_len > 125) {
+            return Err(protocol_error("control frames must not be fragmented nor exceed 125 bytes"));
+        }
+        if payload_len > MAX_PAYLOAD_LEN {
+            return Err(protocol_error("frame payload is too large"));
+        }
+
+        let header_len = offset + 4;
+        let frame_len = header_len + payload_len as usize;
+        if src.len() < frame_len {
+            src.reserve(frame_len - src.len());
+            return Ok(None);
+        }
+
+        let frame = src.split_to(frame_len);
+        let mask = [frame[offset], frame[offset + 1], frame[offset + 2], frame[offset + 3]];
+        let payload = frame[header_len..]
+            .iter()
+            .enumerate()
+            .map(|(i, b)| b ^ mask[i % 4])
+            .collect();
+
+        Ok(Some(Frame {
+            fin,
+            opcode,
+            payload,
+        }))
+    }
+}
+
+impl Encoder for FrameCodec {
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Frame, dst: &mut BytesMut) -> Result<(), io::Error> {
+        let len = frame.payload.len();
+        dst.reserve(len + 10);
+
+        let fin = if frame.fin { 0x80 } else { 0x00 };
+        dst.put_u8(fin | frame.opcode.as_u8());
+        if len < 126 {
+            dst.put_u8(len as u8);
+        } else if len <= 0xFFFF {
+            dst.put_u8(126);
+            dst.put_u16_be(len as u16);
+        } else {
+            dst.put_u8(127);
+            dst.put_u64_be(len as u64);
+        }
+        dst.put_slice(&frame.payload);
+        Ok(())
+    }
+}
+
+pub(crate) fn protocol_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_masked_frames() {
+        // A masked "Hello" from RFC 6455, section 5.7, split in two reads.
+        let mut buf = BytesMut::from(&[0x81, 0x85, 0x37, 0xfa, 0x21][..]);
+        assert_eq!(FrameCodec.decode(&mut buf).unwrap(), None);
+        buf.extend_from_slice(&[0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]);
+        assert_eq!(
+            FrameCodec.decode(&mut buf).unwrap(),
+            Some(Frame::new(OpCode::Text, b"Hello".to_vec()))
+        );
+        assert!(buf.is_empty());
+
+        let mut buf = BytesMut::from(&[0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f][..]);
+        assert!(FrameCodec.decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn encode_unmasked_frames() {
+        let mut buf = BytesMut::new();
+        FrameCodec
+            .encode(Frame::new(OpCode::Text, b"Hello".to_vec()), &mut buf)
+            .unwrap();
+        assert_eq!(&buf[..], &[0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f][..]);
+
+        let mut buf = BytesMut::new();
+        FrameCodec
+            .encode(Frame::new(OpCode::Binary, vec![0; 256]), &mut buf)
+            .unwrap();
+        assert_eq!(&buf[..4], &[0x82, 0x7E, 0x01, 0x00][..]);
+        assert_eq!(buf.len(), 260);
+    }
+}
@@ -0,0 +1,384 @@
+//! WebSocket connections upgraded from HTTP requests.
+
+mod codec;
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::str;
+
+use base64;
+use bytes::{Bytes, BytesMut};
+use futures::{future, Async, AsyncSink, Future, IntoFuture, Poll, Sink, StartSend, Stream};
+use hyper::{Method, Response, StatusCode};
+use hyper::header::Headers;
+use sha1::Sha1;
+use tokio_codec::{Framed, FramedParts};
+use tokio_io::{AsyncRead, AsyncWrite};
+use typemap::Key;
+
+use context::Context;
+use result::{AsyncResult, Failure};
+use streaming::NoEventLoop;
+use self::codec::{protocol_error, Frame, FrameCodec, OpCode, MAX_PAYLOAD_LEN};
+
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+
+/// A message sent over a WebSocket connection.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// A ping. The pong to a ping from the client is sent automatically.
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// A close frame, with the status code and the reason if any.
+    ///
+    /// The close frame from the client is echoed automatically, and then the stream ends.
+    Close(Option<(u16, String)>),
+}
+
+
+/// The error returned when a request is not a valid WebSocket handshake.
+#[derive(Debug)]
+pub struct HandshakeError(&'static str);
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid WebSocket handshake: {}", self.0)
+    }
+}
+
+impl StdError for HandshakeError {
+    fn description(&self) -> &str {
+        "invalid WebSocket handshake"
+    }
+}
+
+
+impl Context {
+    /// Accepts the WebSocket handshake, and calls `on_upgrade` with the connection
+    /// once `101 Switching Protocols` has been sent.
+    ///
+    /// The request fails with `400 Bad Request` if it is not a valid handshake,
+    /// or `426 Upgrade Required` if the client does not speak version 13 of the protocol.
+    /// This requires the event loop of `Server`, which drives the upgraded connection.
+    pub fn websocket<F, R>(mut self, on_upgrade: F) -> AsyncResult
+    where
+        F: FnOnce(WebSocket) -> R + Send + 'static,
+        R: IntoFuture<Item = (), Error = ()>,
+        R::Future: 'static,
+    {
+        if self.handle().is_none() {
+            return future::err(NoEventLoop.into()).boxed();
+        }
+        let accept = match handshake(&self.req.method, &self.req.headers) {
+            Ok(accept) => accept,
+            Err(failure) => return future::err(failure).boxed(),
+        };
+
+        self.ext.insert::<OnUpgrade>(OnUpgrade(Box::new(move |ws| {
+            Box::new(on_upgrade(ws).into_future()) as Box<Future<Item = (), Error = ()>>
+        })));
+
+        let mut res = Response::new().with_status(StatusCode::SwitchingProtocols);
+        res.headers_mut().set_raw("Upgrade", "websocket");
+        res.headers_mut().set_raw("Connection", "Upgrade");
+        res.headers_mut().set_raw("Sec-WebSocket-Accept", accept);
+        self.finish(res)
+    }
+}
+
+/// Validates the handshake, and returns the value of `Sec-WebSocket-Accept`.
+fn handshake(method: &Method, headers: &Headers) -> Result<String, Failure> {
+    if *method != Method::Get {
+        return Err(bad_request("the method must be GET"));
+    }
+    if !has_token(headers, "Upgrade", "websocket") {
+        return Err(bad_request("missing `Upgrade: websocket`"));
+    }
+    if !has_token(headers, "Connection", "upgrade") {
+        return Err(bad_request("missing `Connection: Upgrade`"));
+    }
+    if raw_value(headers, "Sec-WebSocket-Version") != Some("13") {
+        let mut res = Response::new()
+            .with_status(StatusCode::UpgradeRequired)
+            .with_body("Upgrade Required");
+        res.headers_mut().set_raw("Sec-WebSocket-Version", "13");
+        return Err(Failure::from(HandshakeError("unsupported version")).with_response(res));
+    }
+    let key = raw_value(headers, "Sec-WebSocket-Key")
+        .filter(|key| base64::decode(key).map(|key| key.len() == 16).unwrap_or(false))
+        .ok_or_else(|| bad_request("missing or invalid `Sec-WebSocket-Key`"))?;
+    Ok(accept_key(key))
+}
+
+fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(ACCEPT_GUID.as_bytes());
+    base64::encode(&sha1.digest().bytes())
+}
+
+fn has_token(headers: &Headers, name: &str, token: &str) -> bool {
+    headers.get_raw(name).map_or(false, |raw| {
+        raw.iter()
+            .filter_map(|line| str::from_utf8(line).ok())
+            .flat_map(|line| line.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    })
+}
+
+fn raw_value<'a>(headers: &'a Headers, name: &str) -> Option<&'a str> {
+    headers
+        .get_raw(name)
+        .and_then(|raw| raw.one())
+        .and_then(|value| str::from_utf8(value).ok())
+        .map(str::trim)
+}
+
+fn bad_request(reason: &'static str) -> Failure {
+    Failure::from(HandshakeError(reason)).with_response(
+        Response::new()
+            .with_status(StatusCode::BadRequest)
+            .with_body("Bad Request"),
+    )
+}
+
+
+/// The handler of an upgraded connection, registered by `Context::websocket()`.
+pub(crate) struct OnUpgrade(Box<FnOnce(WebSocket) -> Box<Future<Item = (), Error = ()>> + Send>);
+
+impl Key for OnUpgrade {
+    type Value = Self;
+}
+
+impl OnUpgrade {
+    /// Starts the WebSocket session on the connection, with the bytes read beyond the handshake.
+    ///
+    /// Panics of the handler, either when it is called or while polling the session, close
+    /// the connection so that they do not tear down the event loop.
+    pub(crate) fn run<I>(self, io: I, read_buf: Bytes) -> Box<Future<Item = (), Error = ()>>
+    where
+        I: AsyncRead + AsyncWrite + 'static,
+    {
+        let on_upgrade = self.0;
+        let ws = WebSocket::new(Box::new(io), read_buf);
+        match panic::catch_unwind(AssertUnwindSafe(|| on_upgrade(ws))) {
+            Ok(session) => Box::new(AssertUnwindSafe(session).catch_unwind().then(|_| Ok(()))),
+            Err(_) => Box::new(future::err(())),
+        }
+    }
+}
+
+
+trait Io: AsyncRead + AsyncWrite {}
+
+impl<T: AsyncRead + AsyncWrite> Io for T {}
+
+
+/// A WebSocket connection, which is a `Stream` of the messages from the client
+/// and a `Sink` of the messages to the client.
+///
+/// Fragmented messages are reassembled, and pings and the closing handshake are
+/// answered while the stream is polled.
+pub struct WebSocket {
+    framed: Framed<Box<Io>, FrameCodec>,
+    fragments: Option<(OpCode, Vec<u8>)>,
+    reply: Option<Frame>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl WebSocket {
+    fn new(io: Box<Io>, read_buf: Bytes) -> Self {
+        let mut parts = FramedParts::new(io, FrameCodec);
+        parts.read_buf = BytesMut::from(read_buf);
+        WebSocket {
+            framed: Framed::from_parts(parts),
+            fragments: None,
+            reply: None,
+            close_sent: false,
+            close_received: false,
+        }
+    }
+
+    /// Queues the reply to a control frame, replacing the one not sent yet.
+    fn reply(&mut self, frame: Frame) -> Result<(), io::Error> {
+        self.reply = Some(frame);
+        self.poll_reply()?;
+        Ok(())
+    }
+
+    fn poll_reply(&mut self) -> Poll<(), io::Error> {
+        if let Some(frame) = self.reply.take() {
+            if let AsyncSink::NotReady(frame) = self.framed.start_send(frame)? {
+                self.reply = Some(frame);
+                return Ok(Async::NotReady);
+            }
+        }
+        self.framed.poll_complete()
+    }
+
+    fn on_frame(&mut self, frame: Frame) -> Result<Option<Message>, io::Error> {
+        match frame.opcode {
+            OpCode::Ping => {
+                self.reply(Frame::new(OpCode::Pong, frame.payload.clone()))?;
+                Ok(Some(Message::Ping(frame.payload)))
+            }
+            OpCode::Pong => Ok(Some(Message::Pong(frame.payload))),
+            OpCode::Close => {
+                let message = into_message(OpCode::Close, frame.payload)?;
+                self.close_received = true;
+                if !self.close_sent {
+                    self.close_sent = true;
+                    let code = match message {
+                        Message::Close(Some((code, _))) => vec![(code >> 8) as u8, code as u8],
+                        _ => vec![],
+                    };
+                    self.reply(Frame::new(OpCode::Close, code))?;
+                }
+                Ok(Some(message))
+            }
+            OpCode::Text | OpCode::Binary => {
+                if self.fragments.is_some() {
+                    return Err(protocol_error("expected a continuation frame"));
+                }
+                if frame.fin {
+                    return into_message(frame.opcode, frame.payload).map(Some);
+                }
+                self.fragments = Some((frame.opcode, frame.payload));
+                Ok(None)
+            }
+            OpCode::Continuation => {
+                let (opcode, mut payload) = self.fragments
+                    .take()
+                    .ok_or_else(|| protocol_error("unexpected continuation frame"))?;
+                if (payload.len() + frame.payload.len()) as u64 > MAX_PAYLOAD_LEN {
+                    return Err(protocol_error("message is too large"));
+                }
+                payload.extend(frame.payload);
+                if frame.fin {
+                    return into_message(opcode, payload).map(Some);
+                }
+                self.fragments = Some((opcode, payload));
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl Stream for WebSocket {
+    type Item = Message;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Message>, io::Error> {
+        loop {
+            if self.close_received {
+                return match self.poll_reply()? {
+                    Async::Ready(()) => Ok(Async::Ready(None)),
+                    Async::NotReady => Ok(Async::NotReady),
+                };
+            }
+            let frame = match self.framed.poll()? {
+                Async::Ready(Some(frame)) => frame,
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => return Ok(Async::NotReady),
+            };
+            if let Some(message) = self.on_frame(frame)? {
+                return Ok(Async::Ready(Some(message)));
+            }
+        }
+    }
+}
+
+impl Sink for WebSocket {
+    type SinkItem = Message;
+    type SinkError = io::Error;
+
+    fn start_send(&mut self, message: Message) -> StartSend<Message, io::Error> {
+        // Nothing may be sent after the close frame.
+        if self.close_sent {
+            return Ok(AsyncSink::Ready);
+        }
+        if let Async::NotReady = self.poll_reply()? {
+            if self.reply.is_some() {
+                return Ok(AsyncSink::NotReady(message));
+            }
+        }
+        let frame = from_message(message);
+        self.close_sent = frame.opcode == OpCode::Close;
+        match self.framed.start_send(frame)? {
+            AsyncSink::Ready => Ok(AsyncSink::Ready),
+            AsyncSink::NotReady(frame) => {
+                self.close_sent = false;
+                into_message(frame.opcode, frame.payload).map(AsyncSink::NotReady)
+            }
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), io::Error> {
+        self.poll_reply()
+    }
+
+    fn close(&mut self) -> Poll<(), io::Error> {
+        match self.poll_reply()? {
+            Async::Ready(()) => self.framed.close(),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+fn into_message(opcode: OpCode, payload: Vec<u8>) -> Result<Message, io::Error> {
+    match opcode {
+        OpCode::Text => String::from_utf8(payload)
+            .map(Message::Text)
+            .map_err(|_| protocol_error("text message is not valid UTF-8")),
+        OpCode::Binary => Ok(Message::Binary(payload)),
+        OpCode::Ping => Ok(Message::Ping(payload)),
+        OpCode::Pong => Ok(Message::Pong(payload)),
+        OpCode::Close => match payload.len() {
+            0 => Ok(Message::Close(None)),
+            1 => Err(protocol_error("close frame has a truncated status code")),
+            _ => {
+                let code = (u16::from(payload[0]) << 8) | u16::from(payload[1]);
+                let reason = String::from_utf8(payload[2..].to_vec())
+                    .map_err(|_| protocol_error("close reason is not valid UTF-8"))?;
+                Ok(Message::Close(Some((code, reason))))
+            }
+        },
+        OpCode::Continuation => Err(protocol_error("unexpected continuation frame")),
+    }
+}
+
+fn from_message(message: Message) -> Frame {
+    match message {
+        Message::Text(text) => Frame::new(OpCode::Text, text.into_bytes()),
+        Message::Binary(data) => Frame::new(OpCode::Binary, data),
+        Message::Ping(data) => Frame::new(OpCode::Ping, data),
+        Message::Pong(data) => Frame::new(OpCode::Pong, data),
+        Message::Close(None) => Frame::new(OpCode::Close, vec![]),
+        Message::Close(Some((code, reason))) => {
+            let mut payload = vec![(code >> 8) as u8, code as u8];
+            payload.extend(reason.into_bytes());
+            Frame::new(OpCode::Close, payload)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_key_of_rfc_sample() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+}